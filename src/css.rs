@@ -1,17 +1,96 @@
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::source::{LineIndex, Location, Span};
+
+/// An error in a stylesheet or selector list. The stylesheet parser recovers from all errors,
+/// so these are reported as warnings there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Byte offset into the source where the error was detected.
+    pub offset: usize,
+    /// 1-based line number of `offset`.
+    pub line: usize,
+    /// 1-based column (in characters) of `offset`.
+    pub column: usize,
+    /// Description of what the parser was expecting.
+    pub expected: String,
+    /// Description of what was actually found.
+    pub found: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: expected {}, found {}",
+            self.line, self.column, self.expected, self.found
+        )
+    }
+}
+
+impl ParseError {
+    fn new(location: Location, expected: &str, found: String) -> ParseError {
+        ParseError {
+            offset: location.offset,
+            line: location.line,
+            column: location.column,
+            expected: expected.to_string(),
+            found,
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 #[derive(Debug, PartialEq)]
 pub struct Stylesheet {
     pub rules: Vec<Rule>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub struct Rule {
     pub selectors: Vec<Selector>,
     pub declarations: Vec<Declaration>,
+    /// Where the rule was parsed from.
+    pub span: Option<Span>,
+    /// The file the rule was loaded from, if a `loader::StylesheetLoader` loaded it. Rules from a
+    /// `<style>` element have the document's path, with spans counted from the start of the
+    /// document.
+    pub path: Option<Rc<Path>>,
+}
+
+// Source locations are metadata, so they don't take part in comparisons.
+impl PartialEq for Rule {
+    fn eq(&self, other: &Rule) -> bool {
+        self.selectors == other.selectors && self.declarations == other.declarations
+    }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum Selector {
     Simple(SimpleSelector),
+    /// Simple selectors joined by combinators, such as `ul > li`. `subject` is the rightmost
+    /// one, which the matched element itself must match. `context` holds the others from right
+    /// to left, each with the combinator that joins it to the selector on its right.
+    Complex {
+        subject: SimpleSelector,
+        context: Vec<(Combinator, SimpleSelector)>,
+    },
+}
+
+/// How the simple selectors in a `Selector::Complex` relate to each other.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Combinator {
+    /// Whitespace, as in `div p`: an ancestor.
+    Descendant,
+    /// `>`: the parent.
+    Child,
+    /// `+`: the previous sibling element.
+    NextSibling,
+    /// `~`: any earlier sibling element.
+    SubsequentSibling,
 }
 
 #[derive(Debug, PartialEq)]
@@ -19,6 +98,105 @@ pub struct SimpleSelector {
     pub tag_name: Option<String>,
     pub id: Option<String>,
     pub class: Vec<String>,
+    pub attributes: Vec<AttributeSelector>,
+    pub pseudo_classes: Vec<PseudoClass>,
+    /// The pseudo-element that the selector ends with, if any. Only the subject of a selector
+    /// can have one.
+    pub pseudo_element: Option<PseudoElement>,
+}
+
+/// A condition on an attribute, such as `[data-size]` or `[lang|=en i]`.
+#[derive(Debug, PartialEq)]
+pub struct AttributeSelector {
+    /// The attribute name, ASCII-lowercased as HTML attribute names are.
+    pub name: String,
+    /// How the attribute value is compared with `value`, or `None` if the attribute only has to
+    /// be present.
+    pub operator: Option<AttributeOperator>,
+    pub value: String,
+    /// Whether the comparison ignores ASCII case, as requested by the `i` flag.
+    pub case_insensitive: bool,
+}
+
+/// A pseudo-class, such as `:first-child` or `:not(.hidden)`.
+#[derive(Debug, PartialEq)]
+pub enum PseudoClass {
+    /// The root element of the document.
+    Root,
+    /// An element without child elements or text.
+    Empty,
+    FirstChild,
+    LastChild,
+    OnlyChild,
+    /// `:nth-child(an+b of S)`. Only siblings matching the selectors `S`, if there are any, are
+    /// counted.
+    NthChild(Nth, Vec<Selector>),
+    /// `:nth-last-child(an+b of S)`, which counts siblings from the end.
+    NthLastChild(Nth, Vec<Selector>),
+    /// `:nth-of-type(an+b)`, which counts siblings with the same tag name.
+    NthOfType(Nth),
+    /// An element that matches none of the selectors.
+    Not(Vec<Selector>),
+    /// An element that matches any of the selectors.
+    Is(Vec<Selector>),
+    /// Like `Is`, but without adding to the specificity.
+    Where(Vec<Selector>),
+    // Dynamic pseudo-classes, which depend on the state of the user interface:
+    Hover,
+    Focus,
+    Active,
+    Checked,
+    Disabled,
+    /// A link that hasn't been visited.
+    Link,
+    Visited,
+}
+
+/// A pseudo-element, which selects a box generated for an element rather than the element
+/// itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PseudoElement {
+    /// `::before`, generated content at the start of the element.
+    Before,
+    /// `::after`, generated content at the end of the element.
+    After,
+}
+
+/// The `an+b` argument of `:nth-child()` and similar pseudo-classes, which matches the
+/// elements at 1-based positions `a*n + b` for any `n >= 0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Nth {
+    pub a: i32,
+    pub b: i32,
+}
+
+impl Nth {
+    /// Does this match the element at 1-based `index`?
+    pub fn matches(&self, index: i32) -> bool {
+        if self.a == 0 {
+            index == self.b
+        } else {
+            // Widen so that `index - b` can't overflow.
+            let (a, n) = (i64::from(self.a), i64::from(index) - i64::from(self.b));
+            n % a == 0 && n / a >= 0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttributeOperator {
+    /// `=`: the value is exactly `value`.
+    Equals,
+    /// `~=`: one of the whitespace-separated words in the value is `value`.
+    Includes,
+    /// `|=`: the value is `value` or starts with `value` followed by `-`.
+    DashMatch,
+    /// `^=`: the value starts with `value`.
+    Prefix,
+    /// `$=`: the value ends with `value`.
+    Suffix,
+    /// `*=`: the value contains `value`.
+    Substring,
 }
 
 pub type Specificity = (usize, usize, usize);
@@ -26,18 +204,71 @@ pub type Specificity = (usize, usize, usize);
 impl Selector {
     pub fn specificity(&self) -> Specificity {
         // http://www.w3.org/TR/selectors/#specificity
-        let Selector::Simple(ref simple) = *self;
-        let a = simple.id.iter().count();
-        let b = simple.class.len();
-        let c = simple.tag_name.iter().count();
-        (a, b, c)
+        match *self {
+            Selector::Simple(ref simple) => simple.specificity(),
+            Selector::Complex {
+                ref subject,
+                ref context,
+            } => context
+                .iter()
+                .map(|(_, simple)| simple.specificity())
+                .fold(subject.specificity(), add_specificity),
+        }
     }
 }
 
-#[derive(Debug, PartialEq)]
+impl SimpleSelector {
+    pub fn specificity(&self) -> Specificity {
+        let a = self.id.iter().count();
+        let b = self.class.len() + self.attributes.len();
+        let c = self.tag_name.iter().count() + self.pseudo_element.iter().count();
+        self.pseudo_classes
+            .iter()
+            .map(PseudoClass::specificity)
+            .fold((a, b, c), add_specificity)
+    }
+}
+
+impl PseudoClass {
+    pub fn specificity(&self) -> Specificity {
+        match self {
+            // These take the specificity of their most specific argument.
+            PseudoClass::Not(selectors) | PseudoClass::Is(selectors) => max_specificity(selectors),
+            PseudoClass::Where(_) => (0, 0, 0),
+            PseudoClass::NthChild(_, selectors) | PseudoClass::NthLastChild(_, selectors) => {
+                add_specificity((0, 1, 0), max_specificity(selectors))
+            }
+            _ => (0, 1, 0),
+        }
+    }
+}
+
+fn add_specificity((a, b, c): Specificity, (x, y, z): Specificity) -> Specificity {
+    (a + x, b + y, c + z)
+}
+
+fn max_specificity(selectors: &[Selector]) -> Specificity {
+    selectors
+        .iter()
+        .map(Selector::specificity)
+        .max()
+        .unwrap_or((0, 0, 0))
+}
+
+#[derive(Debug)]
 pub struct Declaration {
     pub name: String,
     pub value: Value,
+    /// Whether the value was followed by `!important`.
+    pub important: bool,
+    /// Where the declaration was parsed from.
+    pub span: Option<Span>,
+}
+
+impl PartialEq for Declaration {
+    fn eq(&self, other: &Declaration) -> bool {
+        self.name == other.name && self.value == other.value && self.important == other.important
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -45,6 +276,19 @@ pub enum Value {
     Keyword(String),
     Length(f32, Unit),
     ColorValue(Color),
+    /// A number without a unit, such as `line-height: 1.5`.
+    Number(f32),
+    /// A quoted string, such as `content: "x"`.
+    Str(String),
+    /// `attr(name)`: the value of an attribute of the element.
+    Attr(String),
+    /// `counter(name)`: the value of the innermost counter called `name`.
+    Counter(String),
+    /// `counters(name, separator)`: the values of all counters called `name`, outermost
+    /// first, joined by `separator`.
+    Counters(String, String),
+    /// Several space-separated values, such as `content: "(" attr(title) ")"`.
+    List(Vec<Value>),
     // insert more values here
 }
 
@@ -72,217 +316,1520 @@ pub struct Color {
     pub a: u8,
 }
 
-/// Parse a whole CSS stylesheet.
+/// Parse a whole CSS stylesheet. `@import` rules are ignored.
 pub fn parse(source: String) -> Stylesheet {
-    let mut parser = Parser {
-        pos: 0,
-        input: source,
-    };
-    Stylesheet {
-        rules: parser.parse_rules(),
+    parse_with_imports(source, |_| None)
+}
+
+/// Parse a whole CSS stylesheet, calling `import` with the URL of each `@import` rule to get the
+/// stylesheet it refers to. Imported rules come before the stylesheet's own rules in the cascade.
+pub fn parse_with_imports<F>(source: String, import: F) -> Stylesheet
+where
+    F: FnMut(&str) -> Option<Stylesheet>,
+{
+    parse_with_errors(source, import).0
+}
+
+/// Like `parse_with_imports`, but also return the errors that the parser recovered from. As in
+/// browsers, a declaration or rule containing an error is left out and parsing continues after it.
+pub fn parse_with_errors<F>(source: String, mut import: F) -> (Stylesheet, Vec<ParseError>)
+where
+    F: FnMut(&str) -> Option<Stylesheet>,
+{
+    let mut parser = Parser::new(&source);
+    let mut rules = vec![];
+    for url in parser.parse_imports() {
+        if let Some(imported) = import(&url) {
+            rules.extend(imported.rules);
+        }
+    }
+    rules.extend(parser.parse_rules());
+    (Stylesheet { rules }, parser.into_errors())
+}
+
+/// Parse a list of declarations without surrounding braces, as in a `style` attribute.
+/// Declarations that can't be parsed are left out.
+pub fn parse_declarations(source: String) -> Vec<Declaration> {
+    let mut parser = Parser::new(&source);
+    parser.parse_declaration_list(false)
+}
+
+/// Parse a comma-separated list of selectors, such as the argument to `querySelector`. Fails if
+/// any of the selectors is invalid.
+pub fn parse_selector_list(source: String) -> Result<Vec<Selector>, ParseError> {
+    let mut parser = Parser::new(&source);
+    parser.consume_whitespace();
+    let selectors = parser.parse_selectors()?;
+    match parser.next_token() {
+        None => Ok(selectors),
+        Some(_) => Err(parser.error("',' or end of input")),
     }
 }
 
+/// Split CSS source into tokens, each with the span it was read from. Comments are dropped.
+pub fn tokenize(source: &str) -> Vec<(Token, Span)> {
+    Tokenizer::new(source).tokenize()
+}
+
+/// Parse CSS source into a list of component values, as found in the value of a declaration.
+pub fn parse_component_values(source: &str) -> Vec<ComponentValue> {
+    let mut parser = Parser::new(source);
+    let mut values = vec![];
+    while parser.next_token().is_some() {
+        values.push(parser.consume_component_value());
+    }
+    values
+}
+
+/// A token, as defined by CSS Syntax Level 3.
+/// https://www.w3.org/TR/css-syntax-3/#tokenization
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+    /// A function name and its opening parenthesis, e.g. `rgb(`.
+    Function(String),
+    /// An at-rule name, e.g. `@import`.
+    AtKeyword(String),
+    /// `#` followed by a name. `is_id` says whether the name could be used as an ID selector.
+    Hash {
+        value: String,
+        is_id: bool,
+    },
+    String(String),
+    /// A string with an unescaped newline in it.
+    BadString,
+    /// An unquoted `url(...)`.
+    Url(String),
+    BadUrl,
+    Delim(char),
+    /// A number. `is_integer` says whether it was written without a fraction or exponent, and
+    /// `signed` whether it was written with a leading `+` or `-`.
+    Number {
+        value: f32,
+        is_integer: bool,
+        signed: bool,
+    },
+    Percentage(f32),
+    /// A number with a unit, such as `10px`.
+    Dimension {
+        value: f32,
+        is_integer: bool,
+        unit: String,
+    },
+    Whitespace,
+    /// `<!--`
+    Cdo,
+    /// `-->`
+    Cdc,
+    Colon,
+    Semicolon,
+    Comma,
+    OpenSquare,
+    CloseSquare,
+    OpenParen,
+    CloseParen,
+    OpenCurly,
+    CloseCurly,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Function(name) => write!(f, "{}(", name),
+            Token::AtKeyword(name) => write!(f, "@{}", name),
+            Token::Hash { value, .. } => write!(f, "#{}", value),
+            Token::String(value) => write!(f, "\"{}\"", value),
+            Token::BadString => write!(f, "\""),
+            Token::Url(url) => write!(f, "url({})", url),
+            Token::BadUrl => write!(f, "url("),
+            Token::Delim(c) => write!(f, "{}", c),
+            Token::Number {
+                value,
+                signed: true,
+                ..
+            } if *value >= 0.0 => write!(f, "+{}", value),
+            Token::Number { value, .. } => write!(f, "{}", value),
+            Token::Percentage(value) => write!(f, "{}%", value),
+            Token::Dimension { value, unit, .. } => write!(f, "{}{}", value, unit),
+            Token::Whitespace => write!(f, " "),
+            Token::Cdo => write!(f, "<!--"),
+            Token::Cdc => write!(f, "-->"),
+            Token::Colon => write!(f, ":"),
+            Token::Semicolon => write!(f, ";"),
+            Token::Comma => write!(f, ","),
+            Token::OpenSquare => write!(f, "["),
+            Token::CloseSquare => write!(f, "]"),
+            Token::OpenParen => write!(f, "("),
+            Token::CloseParen => write!(f, ")"),
+            Token::OpenCurly => write!(f, "{{"),
+            Token::CloseCurly => write!(f, "}}"),
+        }
+    }
+}
+
+/// A token, or a function or block with the component values inside it.
+/// https://www.w3.org/TR/css-syntax-3/#component-value
+#[derive(Debug, Clone, PartialEq)]
+pub enum ComponentValue {
+    Token(Token),
+    /// A function such as `rgb(1, 2, 3)`, with its name and arguments.
+    Function(String, Vec<ComponentValue>),
+    /// A `(...)`, `[...]` or `{...}` block, with the token that opened it.
+    Block(Token, Vec<ComponentValue>),
+}
+
+impl fmt::Display for ComponentValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (open, values, close) = match self {
+            ComponentValue::Token(token) => return write!(f, "{}", token),
+            ComponentValue::Function(name, args) => (format!("{}(", name), args, ")"),
+            ComponentValue::Block(open, values) => (open.to_string(), values, closing(open)),
+        };
+        write!(f, "{}", open)?;
+        for value in values {
+            write!(f, "{}", value)?;
+        }
+        write!(f, "{}", close)
+    }
+}
+
+// The text of the token that closes a block opened by `open`.
+fn closing(open: &Token) -> &'static str {
+    match open {
+        Token::OpenSquare => "]",
+        Token::OpenCurly => "}",
+        _ => ")",
+    }
+}
+
+struct Tokenizer<'a> {
+    pos: usize,
+    input: &'a str,
+    lines: LineIndex,
+    /// Errors recovered from so far, such as unterminated comments.
+    errors: Vec<ParseError>,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(input: &'a str) -> Tokenizer<'a> {
+        Tokenizer {
+            pos: 0,
+            input,
+            lines: LineIndex::new(input),
+            errors: vec![],
+        }
+    }
+
+    /// Read all of the tokens in the input.
+    fn tokenize(&mut self) -> Vec<(Token, Span)> {
+        let mut tokens = vec![];
+        loop {
+            self.consume_comments();
+            let start = self.pos;
+            match self.consume_token() {
+                Some(token) => tokens.push((token, self.lines.span(start, self.pos))),
+                None => break,
+            }
+        }
+        tokens
+    }
+
+    /// Record a recoverable error at byte `offset`.
+    fn report(&mut self, offset: usize, expected: &str, found: &str) {
+        let location = self.lines.location(offset);
+        self.errors
+            .push(ParseError::new(location, expected, found.to_string()));
+    }
+
+    /// Skip any comments at the current position.
+    fn consume_comments(&mut self) {
+        while self.input[self.pos..].starts_with("/*") {
+            let start = self.pos;
+            match self.input[self.pos + 2..].find("*/") {
+                Some(end) => self.pos += 2 + end + 2,
+                None => {
+                    self.pos = self.input.len();
+                    self.report(start, "\"*/\"", "end of input");
+                }
+            }
+        }
+    }
+
+    /// Read one token, or return `None` at the end of input.
+    /// https://www.w3.org/TR/css-syntax-3/#consume-token
+    fn consume_token(&mut self) -> Option<Token> {
+        let c = self.consume_char()?;
+        let token = match c {
+            c if is_whitespace(c) => {
+                self.consume_while(is_whitespace);
+                Token::Whitespace
+            }
+            '"' | '\'' => self.consume_string(c),
+            '#' if self.peek(0).is_some_and(is_name_char) || self.is_valid_escape(0) => {
+                let is_id = self.would_start_ident(0);
+                Token::Hash {
+                    value: self.consume_name(),
+                    is_id,
+                }
+            }
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            '[' => Token::OpenSquare,
+            ']' => Token::CloseSquare,
+            '{' => Token::OpenCurly,
+            '}' => Token::CloseCurly,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            ';' => Token::Semicolon,
+            '+' | '-' | '.' if self.would_start_number(-1) => {
+                self.pos -= 1;
+                self.consume_numeric()
+            }
+            '-' if self.input[self.pos..].starts_with("->") => {
+                self.pos += 2;
+                Token::Cdc
+            }
+            '-' | '\\' if self.would_start_ident(-1) => {
+                self.pos -= 1;
+                self.consume_ident_like()
+            }
+            '\\' => {
+                self.report(self.pos - 1, "an escape", "a newline");
+                Token::Delim('\\')
+            }
+            '<' if self.input[self.pos..].starts_with("!--") => {
+                self.pos += 3;
+                Token::Cdo
+            }
+            '@' if self.would_start_ident(0) => Token::AtKeyword(self.consume_name()),
+            '0'..='9' => {
+                self.pos -= 1;
+                self.consume_numeric()
+            }
+            c if is_name_start_char(c) => {
+                self.pos -= c.len_utf8();
+                self.consume_ident_like()
+            }
+            c => Token::Delim(c),
+        };
+        Some(token)
+    }
+
+    /// Read a string whose opening quote has been consumed.
+    fn consume_string(&mut self, quote: char) -> Token {
+        let mut value = String::new();
+        loop {
+            match self.peek(0) {
+                None => {
+                    self.report(self.pos, &format!("{:?}", quote), "end of input");
+                    break;
+                }
+                Some(c) if c == quote => {
+                    self.consume_char();
+                    break;
+                }
+                Some('\n' | '\r' | '\x0C') => {
+                    // The newline is left to start the next token.
+                    self.report(self.pos, &format!("{:?}", quote), "a newline");
+                    return Token::BadString;
+                }
+                Some('\\') => match self.peek(1) {
+                    None => {
+                        self.consume_char();
+                    }
+                    Some('\n' | '\r' | '\x0C') => {
+                        // An escaped newline continues the string on the next line.
+                        self.consume_char();
+                        self.consume_newline();
+                    }
+                    Some(_) => {
+                        self.consume_char();
+                        value.push(self.consume_escape());
+                    }
+                },
+                Some(_) => value.push(self.consume_char().unwrap()),
+            }
+        }
+        Token::String(value)
+    }
+
+    /// Read a number, percentage or dimension.
+    fn consume_numeric(&mut self) -> Token {
+        let signed = matches!(self.peek(0), Some('+' | '-'));
+        let (value, is_integer) = self.consume_number();
+        if self.would_start_ident(0) {
+            Token::Dimension {
+                value,
+                is_integer,
+                unit: self.consume_name(),
+            }
+        } else if self.peek(0) == Some('%') {
+            self.consume_char();
+            Token::Percentage(value)
+        } else {
+            Token::Number {
+                value,
+                is_integer,
+                signed,
+            }
+        }
+    }
+
+    /// Read a number such as `-5`, `.5` or `1e3`.
+    fn consume_number(&mut self) -> (f32, bool) {
+        let start = self.pos;
+        let mut is_integer = true;
+        if let Some('+' | '-') = self.peek(0) {
+            self.consume_char();
+        }
+        self.consume_while(|c| c.is_ascii_digit());
+        if self.peek(0) == Some('.') && self.peek(1).is_some_and(|c| c.is_ascii_digit()) {
+            is_integer = false;
+            self.consume_char();
+            self.consume_while(|c| c.is_ascii_digit());
+        }
+        let exponent = match (self.peek(0), self.peek(1), self.peek(2)) {
+            (Some('e' | 'E'), Some('+' | '-'), Some(c)) if c.is_ascii_digit() => 2,
+            (Some('e' | 'E'), Some(c), _) if c.is_ascii_digit() => 1,
+            _ => 0,
+        };
+        if exponent > 0 {
+            is_integer = false;
+            self.pos += exponent;
+            self.consume_while(|c| c.is_ascii_digit());
+        }
+        (self.input[start..self.pos].parse().unwrap(), is_integer)
+    }
+
+    /// Read an identifier, function or URL.
+    fn consume_ident_like(&mut self) -> Token {
+        let name = self.consume_name();
+        if self.peek(0) != Some('(') {
+            return Token::Ident(name);
+        }
+        self.consume_char();
+        if !name.eq_ignore_ascii_case("url") {
+            return Token::Function(name);
+        }
+
+        // A quoted URL is a string argument to a `url(` function.
+        let rest = self.input[self.pos..].trim_start_matches(is_whitespace);
+        if rest.starts_with(['"', '\'']) {
+            Token::Function(name)
+        } else {
+            self.consume_url()
+        }
+    }
+
+    /// Read an unquoted URL whose `url(` has been consumed.
+    fn consume_url(&mut self) -> Token {
+        let mut url = String::new();
+        self.consume_while(is_whitespace);
+        loop {
+            match self.consume_char() {
+                Some(')') => break,
+                None => {
+                    self.report(self.pos, "')'", "end of input");
+                    break;
+                }
+                Some(c) if is_whitespace(c) => {
+                    self.consume_while(is_whitespace);
+                    match self.consume_char() {
+                        Some(')') => break,
+                        None => {
+                            self.report(self.pos, "')'", "end of input");
+                            break;
+                        }
+                        Some(_) => {
+                            self.report(self.pos - 1, "')'", "more of the URL after whitespace");
+                            return self.consume_bad_url();
+                        }
+                    }
+                }
+                Some(c @ ('"' | '\'' | '(')) => {
+                    self.report(self.pos - 1, "')'", &format!("{:?}", c));
+                    return self.consume_bad_url();
+                }
+                Some('\\') if self.is_valid_escape(-1) => url.push(self.consume_escape()),
+                Some('\\') => {
+                    self.report(self.pos - 1, "an escape", "a newline");
+                    return self.consume_bad_url();
+                }
+                Some(c) => url.push(c),
+            }
+        }
+        Token::Url(url)
+    }
+
+    /// Skip the rest of a bad URL, up to its closing parenthesis.
+    fn consume_bad_url(&mut self) -> Token {
+        loop {
+            match self.consume_char() {
+                Some(')') | None => break,
+                Some('\\') if self.is_valid_escape(-1) => {
+                    self.consume_escape();
+                }
+                Some(_) => {}
+            }
+        }
+        Token::BadUrl
+    }
+
+    /// Read a name, such as an identifier or the unit of a dimension, decoding escapes.
+    fn consume_name(&mut self) -> String {
+        let mut name = String::new();
+        loop {
+            match self.peek(0) {
+                Some(c) if is_name_char(c) => name.push(self.consume_char().unwrap()),
+                Some('\\') if self.is_valid_escape(0) => {
+                    self.consume_char();
+                    name.push(self.consume_escape());
+                }
+                _ => return name,
+            }
+        }
+    }
+
+    /// Read an escape whose backslash has been consumed.
+    fn consume_escape(&mut self) -> char {
+        let hex_digits = self.input[self.pos..]
+            .chars()
+            .take(6)
+            .take_while(|c| c.is_ascii_hexdigit())
+            .count();
+        if hex_digits == 0 {
+            return match self.consume_char() {
+                Some(c) => c,
+                None => {
+                    self.report(self.pos, "an escaped character", "end of input");
+                    '\u{FFFD}'
+                }
+            };
+        }
+
+        let value = u32::from_str_radix(&self.input[self.pos..self.pos + hex_digits], 16).unwrap();
+        self.pos += hex_digits;
+        // A single whitespace character ends the escape.
+        if self.peek(0).is_some_and(is_whitespace) {
+            self.consume_newline();
+        }
+        match char::from_u32(value) {
+            Some('\0') | None => '\u{FFFD}',
+            Some(c) => c,
+        }
+    }
+
+    /// Consume one whitespace character, treating `\r\n` as one.
+    fn consume_newline(&mut self) {
+        if self.input[self.pos..].starts_with("\r\n") {
+            self.pos += 1;
+        }
+        self.consume_char();
+    }
+
+    /// Is the character at `offset` (relative to the current one) a backslash starting an escape?
+    fn is_valid_escape(&self, offset: isize) -> bool {
+        self.peek(offset) == Some('\\')
+            && !matches!(self.peek(offset + 1), None | Some('\n' | '\r' | '\x0C'))
+    }
+
+    /// Would the characters at `offset` start an identifier?
+    fn would_start_ident(&self, offset: isize) -> bool {
+        match self.peek(offset) {
+            Some('-') => {
+                matches!(self.peek(offset + 1), Some(c) if is_name_start_char(c) || c == '-')
+                    || self.is_valid_escape(offset + 1)
+            }
+            Some('\\') => self.is_valid_escape(offset),
+            Some(c) => is_name_start_char(c),
+            None => false,
+        }
+    }
+
+    /// Would the characters at `offset` start a number?
+    fn would_start_number(&self, offset: isize) -> bool {
+        let is_digit = |offset| self.peek(offset).is_some_and(|c: char| c.is_ascii_digit());
+        match self.peek(offset) {
+            Some('+' | '-') => {
+                is_digit(offset + 1) || (self.peek(offset + 1) == Some('.') && is_digit(offset + 2))
+            }
+            Some('.') => is_digit(offset + 1),
+            _ => is_digit(offset),
+        }
+    }
+
+    /// Read the character `offset` characters after the current one; -1 is the previous one.
+    fn peek(&self, offset: isize) -> Option<char> {
+        if offset < 0 {
+            self.input[..self.pos]
+                .chars()
+                .rev()
+                .nth((-offset - 1) as usize)
+        } else {
+            self.input[self.pos..].chars().nth(offset as usize)
+        }
+    }
+
+    /// Consume characters while `test` returns true.
+    fn consume_while<F>(&mut self, test: F)
+    where
+        F: Fn(char) -> bool,
+    {
+        while self.peek(0).is_some_and(&test) {
+            self.consume_char();
+        }
+    }
+
+    /// Return the current character, and advance self.pos to the next character.
+    fn consume_char(&mut self) -> Option<char> {
+        let c = self.input[self.pos..].chars().next()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0C')
+}
+
+fn is_name_start_char(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || !c.is_ascii()
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start_char(c) || c.is_ascii_digit() || c == '-'
+}
+
+type ParseResult<T> = Result<T, ParseError>;
+
 struct Parser {
+    tokens: Vec<(Token, Span)>,
     pos: usize,
-    input: String,
+    /// The location of the end of input.
+    end: Location,
+    /// Errors recovered from so far.
+    errors: Vec<ParseError>,
 }
 
 impl Parser {
+    fn new(input: &str) -> Parser {
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        Parser {
+            tokens,
+            pos: 0,
+            end: tokenizer.lines.location(input.len()),
+            errors: tokenizer.errors,
+        }
+    }
+
+    /// Return the errors from tokenizing and parsing, in source order.
+    fn into_errors(mut self) -> Vec<ParseError> {
+        self.errors.sort_by_key(|err| err.offset);
+        self.errors
+    }
+
+    /// The source span from the token at `start` to the end of the previous token.
+    fn span_from(&self, start: usize) -> Option<Span> {
+        Some(Span {
+            start: self.tokens[start].1.start,
+            end: self.tokens[self.pos - 1].1.end,
+        })
+    }
+
+    /// Build an error at the current token, expecting `expected` but finding that token.
+    fn error(&self, expected: &str) -> ParseError {
+        let found = match self.next_token() {
+            None => String::from("end of input"),
+            Some(Token::Whitespace) => String::from("whitespace"),
+            Some(token) => format!("'{}'", token),
+        };
+        ParseError::new(self.location(), expected, found)
+    }
+
+    /// The location of the current token.
+    fn location(&self) -> Location {
+        match self.tokens.get(self.pos) {
+            Some((_, span)) => span.start,
+            None => self.end,
+        }
+    }
+
+    /// Parse the `@import` rules at the start of a stylesheet, returning their URLs.
+    fn parse_imports(&mut self) -> Vec<String> {
+        // An `@charset` rule was only needed to decode the stylesheet, which has been done.
+        self.consume_top_level_whitespace();
+        if matches!(self.next_token(), Some(Token::AtKeyword(name)) if name.eq_ignore_ascii_case("charset"))
+        {
+            self.skip_until(&[Token::Semicolon]);
+            self.consume_token();
+        }
+
+        let mut urls = vec![];
+        loop {
+            self.consume_top_level_whitespace();
+            match self.next_token() {
+                Some(Token::AtKeyword(name)) if name.eq_ignore_ascii_case("import") => {}
+                _ => break,
+            }
+            match self.parse_import() {
+                Ok(url) => urls.push(url),
+                Err(err) => {
+                    self.errors.push(err);
+                    self.skip_until(&[Token::Semicolon]);
+                    self.consume_token();
+                }
+            }
+        }
+        urls
+    }
+
+    /// Parse `@import url(<url>) <media>;` or `@import "<url>" <media>;`.
+    fn parse_import(&mut self) -> ParseResult<String> {
+        self.consume_token();
+        self.consume_whitespace();
+        let url = match self.next_token().cloned() {
+            Some(Token::Url(url) | Token::String(url)) => {
+                self.consume_token();
+                url
+            }
+            Some(Token::Function(name)) if name.eq_ignore_ascii_case("url") => {
+                self.consume_token();
+                self.consume_whitespace();
+                let url = match self.next_token().cloned() {
+                    Some(Token::String(url)) => url,
+                    _ => return Err(self.error("a string")),
+                };
+                self.consume_token();
+                self.consume_whitespace();
+                self.expect_token(Token::CloseParen)?;
+                url
+            }
+            _ => return Err(self.error("a URL")),
+        };
+
+        // Media queries aren't supported, so the stylesheet is imported unconditionally.
+        self.skip_until(&[Token::Semicolon]);
+        self.expect_token(Token::Semicolon)?;
+        Ok(url)
+    }
+
     /// Parse a list of rule sets, seperated by optional whitespace.
     fn parse_rules(&mut self) -> Vec<Rule> {
         let mut rules = vec![];
         loop {
-            self.consume_whitespace();
-            if self.eof() {
-                break;
+            self.consume_top_level_whitespace();
+            match self.next_token() {
+                None => break,
+                Some(Token::AtKeyword(_)) => self.skip_at_rule(),
+                Some(_) => match self.parse_rule() {
+                    Ok(rule) => rules.push(rule),
+                    Err(err) => self.errors.push(err),
+                },
             }
-            rules.push(self.parse_rule());
         }
         rules
     }
 
-    /// Parse a rule set: `<selectors> { <declarations> }`.
-    pub fn parse_rule(&mut self) -> Rule {
-        Rule {
-            selectors: self.parse_selectors(),
-            declarations: self.parse_declarations(),
+    /// Skip an at-rule, none of which are supported after the leading `@import` rules.
+    fn skip_at_rule(&mut self) {
+        self.errors.push(self.error("a style rule"));
+        self.consume_token();
+
+        // An at-rule ends with a semicolon or a block.
+        self.skip_until(&[Token::Semicolon, Token::OpenCurly]);
+        if self.next_token().is_some() {
+            self.consume_component_value();
         }
     }
 
-    /// Parse a comma-separated list of selectors
-    fn parse_selectors(&mut self) -> Vec<Selector> {
+    /// Parse a rule set: `<selectors> { <declarations> }`. If the selectors are invalid, the
+    /// whole rule is skipped.
+    pub fn parse_rule(&mut self) -> ParseResult<Rule> {
+        let start = self.pos;
+        let selectors = match self.parse_selectors() {
+            Ok(selectors) if self.next_token() == Some(&Token::OpenCurly) => Ok(selectors),
+            Ok(_) => Err(self.error("'{'")),
+            Err(err) => Err(err),
+        };
+        let selectors = match selectors {
+            Ok(selectors) => selectors,
+            Err(err) => {
+                self.skip_until(&[Token::OpenCurly]);
+                if self.next_token().is_some() {
+                    self.consume_component_value();
+                }
+                return Err(err);
+            }
+        };
+        let declarations = self.parse_declarations();
+        Ok(Rule {
+            selectors,
+            declarations,
+            span: self.span_from(start),
+            path: None,
+        })
+    }
+
+    /// Parse a comma-separated list of selectors, ending at a `{`, a `)` or the end of input.
+    fn parse_selectors(&mut self) -> ParseResult<Vec<Selector>> {
         let mut selectors = vec![];
         loop {
-            selectors.push(Selector::Simple(self.parse_simple_selector()));
-            self.consume_whitespace();
-            match self.next_char() {
-                ',' => {
-                    self.consume_char();
+            selectors.push(self.parse_selector()?);
+            match self.next_token() {
+                // The start of parse_declarations, or the end of a pseudo-class argument.
+                None | Some(Token::OpenCurly | Token::CloseParen) => break,
+                Some(Token::Comma) => {
+                    self.consume_token();
                     self.consume_whitespace();
                 }
-                '{' => break, // start of parse_declarations
-                c => panic!("Unexpected character {} in selector list", c),
+                Some(_) => return Err(self.error("',' or '{'")),
             }
         }
 
         // Return selectors with highest specificity first, for use in matching.
-        selectors.sort_by(|a, b| b.specificity().cmp(&a.specificity()));
-        selectors
+        selectors.sort_by_key(|s| std::cmp::Reverse(s.specificity()));
+        Ok(selectors)
+    }
+
+    /// Parse one selector: simple selectors joined by combinators, e.g. `ul > li.item a`.
+    /// Consumes any whitespace after it.
+    fn parse_selector(&mut self) -> ParseResult<Selector> {
+        let mut subject = self.expect_simple_selector()?;
+        let mut context = vec![];
+        loop {
+            let whitespace = self.next_token() == Some(&Token::Whitespace);
+            self.consume_whitespace();
+            let combinator = match self.next_token() {
+                Some(Token::Delim('>')) => Combinator::Child,
+                Some(Token::Delim('+')) => Combinator::NextSibling,
+                Some(Token::Delim('~')) => Combinator::SubsequentSibling,
+                None | Some(Token::Comma | Token::OpenCurly | Token::CloseParen) => break,
+                Some(_) if whitespace => Combinator::Descendant,
+                Some(_) => return Err(self.error("',' or '{'")),
+            };
+            if subject.pseudo_element.is_some() {
+                return Err(self.error("',' or '{' after a pseudo-element"));
+            }
+            if combinator != Combinator::Descendant {
+                self.consume_token();
+                self.consume_whitespace();
+            }
+            let next = self.expect_simple_selector()?;
+            context.push((combinator, std::mem::replace(&mut subject, next)));
+        }
+
+        if context.is_empty() {
+            Ok(Selector::Simple(subject))
+        } else {
+            context.reverse();
+            Ok(Selector::Complex { subject, context })
+        }
+    }
+
+    /// Parse a simple selector, failing if there isn't one.
+    fn expect_simple_selector(&mut self) -> ParseResult<SimpleSelector> {
+        let start = self.pos;
+        let selector = self.parse_simple_selector()?;
+        if self.pos == start {
+            return Err(self.error("a selector"));
+        }
+        Ok(selector)
     }
 
-    /// Parse one simple selector, e.g.: `type#id.class1.class2.class3`
-    fn parse_simple_selector(&mut self) -> SimpleSelector {
+    /// Parse one simple selector, e.g.: `type#id.class1.class2[attr=value]`
+    fn parse_simple_selector(&mut self) -> ParseResult<SimpleSelector> {
         let mut selector = SimpleSelector {
             tag_name: None,
             id: None,
             class: vec![],
+            attributes: vec![],
+            pseudo_classes: vec![],
+            pseudo_element: None,
         };
-        while !self.eof() {
-            match self.next_char() {
-                '#' => {
-                    self.consume_char();
-                    selector.id = Some(self.parse_identifier());
+        loop {
+            match self.next_token().cloned() {
+                Some(Token::Hash { value, is_id: true }) => {
+                    self.consume_token();
+                    selector.id = Some(value);
                 }
-                '.' => {
-                    self.consume_char();
-                    selector.class.push(self.parse_identifier());
+                Some(Token::Delim('.')) => {
+                    self.consume_token();
+                    match self.next_token().cloned() {
+                        Some(Token::Ident(class)) => {
+                            self.consume_token();
+                            selector.class.push(class);
+                        }
+                        _ => return Err(self.error("a class name")),
+                    }
+                }
+                Some(Token::OpenSquare) => {
+                    self.consume_token();
+                    selector.attributes.push(self.parse_attribute_selector()?);
                 }
-                '*' => {
+                Some(Token::Colon) => {
+                    self.consume_token();
+                    match self.parse_pseudo_element()? {
+                        // Nothing can follow a pseudo-element in the same simple selector.
+                        Some(pseudo_element) => {
+                            selector.pseudo_element = Some(pseudo_element);
+                            break;
+                        }
+                        None => selector.pseudo_classes.push(self.parse_pseudo_class()?),
+                    }
+                }
+                Some(Token::Delim('*')) => {
                     // universal selector
-                    self.consume_char();
+                    self.consume_token();
                 }
-                c if valid_identifier_char(c) => {
-                    selector.tag_name = Some(self.parse_identifier());
+                Some(Token::Ident(name)) => {
+                    self.consume_token();
+                    selector.tag_name = Some(name);
                 }
                 _ => break,
             }
         }
-        selector
+        Ok(selector)
+    }
+
+    /// Parse the inside of an attribute selector, `name op value flag]`, whose `[` has been
+    /// consumed.
+    fn parse_attribute_selector(&mut self) -> ParseResult<AttributeSelector> {
+        self.consume_whitespace();
+        let name = match self.next_token().cloned() {
+            Some(Token::Ident(name)) => name.to_ascii_lowercase(),
+            _ => return Err(self.error("an attribute name")),
+        };
+        self.consume_token();
+        self.consume_whitespace();
+
+        let mut selector = AttributeSelector {
+            name,
+            operator: None,
+            value: String::new(),
+            case_insensitive: false,
+        };
+        if self.next_token() == Some(&Token::CloseSquare) {
+            self.consume_token();
+            return Ok(selector);
+        }
+
+        let operator = match self.next_token() {
+            Some(Token::Delim('=')) => AttributeOperator::Equals,
+            Some(Token::Delim('~')) => AttributeOperator::Includes,
+            Some(Token::Delim('|')) => AttributeOperator::DashMatch,
+            Some(Token::Delim('^')) => AttributeOperator::Prefix,
+            Some(Token::Delim('$')) => AttributeOperator::Suffix,
+            Some(Token::Delim('*')) => AttributeOperator::Substring,
+            _ => return Err(self.error("']' or an attribute operator")),
+        };
+        self.consume_token();
+        if operator != AttributeOperator::Equals {
+            self.expect_token(Token::Delim('='))?;
+        }
+        selector.operator = Some(operator);
+        self.consume_whitespace();
+
+        selector.value = match self.next_token().cloned() {
+            Some(Token::Ident(value) | Token::String(value)) => value,
+            _ => return Err(self.error("an attribute value")),
+        };
+        self.consume_token();
+        self.consume_whitespace();
+
+        if let Some(Token::Ident(flag)) = self.next_token() {
+            match &*flag.to_ascii_lowercase() {
+                "i" => selector.case_insensitive = true,
+                "s" => selector.case_insensitive = false,
+                _ => return Err(self.error("'i' or 's'")),
+            }
+            self.consume_token();
+            self.consume_whitespace();
+        }
+        self.expect_token(Token::CloseSquare)?;
+        Ok(selector)
+    }
+
+    /// Parse a pseudo-element whose first `:` has been consumed, or return `None` if this is a
+    /// pseudo-class instead. `:before` and `:after` are accepted with one colon, as in CSS 2.
+    fn parse_pseudo_element(&mut self) -> ParseResult<Option<PseudoElement>> {
+        let double_colon = self.next_token() == Some(&Token::Colon);
+        let name_pos = self.pos + double_colon as usize;
+        let pseudo_element = match self.tokens.get(name_pos) {
+            Some((Token::Ident(name), _)) => match &*name.to_ascii_lowercase() {
+                "before" => Some(PseudoElement::Before),
+                "after" => Some(PseudoElement::After),
+                _ => None,
+            },
+            _ => None,
+        };
+        match pseudo_element {
+            Some(_) => self.pos = name_pos + 1,
+            None if double_colon => {
+                self.consume_token();
+                return Err(self.error("a supported pseudo-element"));
+            }
+            None => {}
+        }
+        Ok(pseudo_element)
+    }
+
+    /// Parse a pseudo-class whose `:` has been consumed.
+    fn parse_pseudo_class(&mut self) -> ParseResult<PseudoClass> {
+        let pseudo_class = match self.next_token().cloned() {
+            Some(Token::Ident(name)) => match &*name.to_ascii_lowercase() {
+                "root" => PseudoClass::Root,
+                "empty" => PseudoClass::Empty,
+                "first-child" => PseudoClass::FirstChild,
+                "last-child" => PseudoClass::LastChild,
+                "only-child" => PseudoClass::OnlyChild,
+                "hover" => PseudoClass::Hover,
+                "focus" => PseudoClass::Focus,
+                "active" => PseudoClass::Active,
+                "checked" => PseudoClass::Checked,
+                "disabled" => PseudoClass::Disabled,
+                "link" => PseudoClass::Link,
+                "visited" => PseudoClass::Visited,
+                _ => return Err(self.error("a supported pseudo-class")),
+            },
+            Some(Token::Function(name)) => {
+                let start = self.pos;
+                self.consume_token();
+                self.consume_whitespace();
+                let pseudo_class = match &*name.to_ascii_lowercase() {
+                    "nth-child" => {
+                        let (nth, selectors) = self.parse_nth_argument()?;
+                        PseudoClass::NthChild(nth, selectors)
+                    }
+                    "nth-last-child" => {
+                        let (nth, selectors) = self.parse_nth_argument()?;
+                        PseudoClass::NthLastChild(nth, selectors)
+                    }
+                    "nth-of-type" => match self.parse_nth_argument()? {
+                        (nth, selectors) if selectors.is_empty() => PseudoClass::NthOfType(nth),
+                        _ => return Err(self.error("')'")),
+                    },
+                    "not" => PseudoClass::Not(self.parse_selectors()?),
+                    "is" => PseudoClass::Is(self.parse_selectors()?),
+                    "where" => PseudoClass::Where(self.parse_selectors()?),
+                    _ => {
+                        self.pos = start;
+                        return Err(self.error("a supported pseudo-class"));
+                    }
+                };
+                self.consume_whitespace();
+                if self.next_token() != Some(&Token::CloseParen) {
+                    return Err(self.error("')'"));
+                }
+                pseudo_class
+            }
+            _ => return Err(self.error("a pseudo-class")),
+        };
+        self.consume_token();
+        Ok(pseudo_class)
+    }
+
+    /// Parse the `an+b [of S]` argument of `:nth-child()` and similar pseudo-classes, up to the
+    /// closing parenthesis. https://www.w3.org/TR/css-syntax-3/#anb-microsyntax
+    fn parse_nth_argument(&mut self) -> ParseResult<(Nth, Vec<Selector>)> {
+        let start = self.location();
+        let mut tokens = vec![];
+        loop {
+            match self.next_token() {
+                None | Some(Token::CloseParen) => break,
+                Some(Token::Ident(of)) if of.eq_ignore_ascii_case("of") => break,
+                Some(token) => tokens.push(token.clone()),
+            }
+            self.consume_token();
+        }
+        let Some(nth) = parse_nth(&tokens) else {
+            let found: String = tokens.iter().map(|token| token.to_string()).collect();
+            let found = found.trim();
+            return Err(ParseError::new(start, "an+b", format!("'{}'", found)));
+        };
+
+        let mut selectors = vec![];
+        if self.next_token() != Some(&Token::CloseParen) && self.next_token().is_some() {
+            self.consume_token(); // `of`
+            self.consume_whitespace();
+            selectors = self.parse_selectors()?;
+        }
+        Ok((nth, selectors))
     }
 
     /// Parse a list of declarations enclosed in `{ ... }`.
     fn parse_declarations(&mut self) -> Vec<Declaration> {
-        assert_eq!(self.consume_char(), '{');
+        self.consume_token(); // '{'
+        self.parse_declaration_list(true)
+    }
+
+    /// Parse declarations up to the end of input or, if `in_block`, the closing `}`. Invalid
+    /// declarations are skipped up to the next semicolon.
+    fn parse_declaration_list(&mut self, in_block: bool) -> Vec<Declaration> {
+        let stops: &[Token] = if in_block {
+            &[Token::Semicolon, Token::CloseCurly]
+        } else {
+            &[Token::Semicolon]
+        };
         let mut declarations = vec![];
         loop {
             self.consume_whitespace();
-            if self.next_char() == '}' {
-                self.consume_char();
-                break;
+            match self.next_token() {
+                None => {
+                    if in_block {
+                        self.errors.push(self.error("'}'"));
+                    }
+                    break;
+                }
+                Some(Token::CloseCurly) if in_block => {
+                    self.consume_token();
+                    break;
+                }
+                Some(Token::Semicolon) => {
+                    self.consume_token();
+                }
+                Some(_) => match self.parse_declaration(stops) {
+                    Ok(declaration) => declarations.push(declaration),
+                    Err(err) => {
+                        self.errors.push(err);
+                        self.skip_until(stops);
+                    }
+                },
             }
-            declarations.push(self.parse_declaration());
         }
         declarations
     }
 
-    /// Parse one `<property>: <value>;` declaration.
-    fn parse_declaration(&mut self) -> Declaration {
-        let property_name = self.parse_identifier();
-        self.consume_whitespace();
-        assert_eq!(self.consume_char(), ':');
+    /// Parse one `<property>: <value>;` declaration, whose value ends at one of `stops`.
+    fn parse_declaration(&mut self, stops: &[Token]) -> ParseResult<Declaration> {
+        let start = self.pos;
+        let property_name = match self.next_token().cloned() {
+            Some(Token::Ident(name)) => name,
+            _ => return Err(self.error("a property name")),
+        };
+        self.consume_token();
         self.consume_whitespace();
-        let value = self.parse_value();
+        self.expect_token(Token::Colon)?;
         self.consume_whitespace();
-        assert_eq!(self.consume_char(), ';');
 
-        Declaration {
+        let value_start = self.location();
+        let mut components = vec![];
+        while self
+            .next_token()
+            .is_some_and(|token| !stops.contains(token))
+        {
+            components.push(self.consume_component_value());
+        }
+        trim_trailing_whitespace(&mut components);
+        let important = strip_important(&mut components);
+        let value = match &*property_name {
+            "content" => parse_content(&components),
+            "counter-reset" => parse_counter_changes(&components, 0),
+            "counter-increment" => parse_counter_changes(&components, 1),
+            _ => parse_value(&components),
+        };
+        let value = match value {
+            Some(value) => value,
+            None if components.is_empty() => return Err(self.error("a value")),
+            None => {
+                let found: String = components.iter().map(|c| c.to_string()).collect();
+                return Err(ParseError::new(
+                    value_start,
+                    "a supported value",
+                    format!("'{}'", found),
+                ));
+            }
+        };
+
+        // The semicolon is optional after the last declaration in a block.
+        if self.next_token() == Some(&Token::Semicolon) {
+            self.consume_token();
+        }
+
+        Ok(Declaration {
             name: property_name,
             value,
-        }
+            important,
+            span: self.span_from(start),
+        })
     }
 
-    // Methods for parsing values:
+    /// Read a token, or a whole function or block.
+    /// https://www.w3.org/TR/css-syntax-3/#consume-component-value
+    fn consume_component_value(&mut self) -> ComponentValue {
+        let (token, close) = match self.consume_token() {
+            Some(Token::Function(name)) => (Token::Function(name), Token::CloseParen),
+            Some(Token::OpenParen) => (Token::OpenParen, Token::CloseParen),
+            Some(Token::OpenSquare) => (Token::OpenSquare, Token::CloseSquare),
+            Some(Token::OpenCurly) => (Token::OpenCurly, Token::CloseCurly),
+            Some(token) => return ComponentValue::Token(token),
+            None => panic!("no component value at the end of input"),
+        };
 
-    fn parse_value(&mut self) -> Value {
-        match self.next_char() {
-            '0'..='9' => self.parse_length(),
-            '#' => self.parse_color(),
-            _ => Value::Keyword(self.parse_identifier()),
+        let mut values = vec![];
+        loop {
+            match self.next_token() {
+                Some(next) if *next == close => {
+                    self.consume_token();
+                    break;
+                }
+                Some(_) => values.push(self.consume_component_value()),
+                None => {
+                    self.errors.push(self.error(&format!("'{}'", close)));
+                    break;
+                }
+            }
+        }
+        match token {
+            Token::Function(name) => ComponentValue::Function(name, values),
+            open => ComponentValue::Block(open, values),
         }
     }
 
-    fn parse_length(&mut self) -> Value {
-        Value::Length(self.parse_float(), self.parse_unit())
+    /// Skip component values up to the next of the `stops` tokens that isn't inside a block, or
+    /// to the end of input.
+    fn skip_until(&mut self, stops: &[Token]) {
+        while self
+            .next_token()
+            .is_some_and(|token| !stops.contains(token))
+        {
+            self.consume_component_value();
+        }
     }
 
-    fn parse_float(&mut self) -> f32 {
-        let s = self.consume_while(|c| match c {
-            '0'..='9' | '.' => true,
-            _ => false,
-        });
-        s.parse().unwrap()
+    /// Consume the given token, or fail without consuming anything.
+    fn expect_token(&mut self, expected: Token) -> ParseResult<()> {
+        if self.next_token() == Some(&expected) {
+            self.consume_token();
+            Ok(())
+        } else {
+            Err(self.error(&format!("'{}'", expected)))
+        }
     }
 
-    fn parse_unit(&mut self) -> Unit {
-        match &*self.parse_identifier().to_ascii_lowercase() {
-            "px" => Unit::Px,
-            _ => panic!("unrecognized unit"),
+    /// Consume and discard zero or more whitespace tokens.
+    fn consume_whitespace(&mut self) {
+        while self.next_token() == Some(&Token::Whitespace) {
+            self.consume_token();
         }
     }
 
-    fn parse_color(&mut self) -> Value {
-        assert_eq!(self.consume_char(), '#');
-        Value::ColorValue(Color {
-            r: self.parse_hex_pair(),
-            g: self.parse_hex_pair(),
-            b: self.parse_hex_pair(),
-            a: 255,
-        })
+    /// Consume whitespace between rules, where `<!--` and `-->` are ignored so that stylesheets
+    /// can be hidden from old browsers.
+    fn consume_top_level_whitespace(&mut self) {
+        while let Some(Token::Whitespace | Token::Cdo | Token::Cdc) = self.next_token() {
+            self.consume_token();
+        }
     }
 
-    /// Parse two hexadecimal digits.
-    fn parse_hex_pair(&mut self) -> u8 {
-        let s = &self.input[self.pos..self.pos + 2];
-        self.pos += 2;
-        u8::from_str_radix(s, 16).unwrap()
+    /// Return the current token, and advance to the next one.
+    fn consume_token(&mut self) -> Option<Token> {
+        let (token, _) = self.tokens.get(self.pos)?.clone();
+        self.pos += 1;
+        Some(token)
     }
 
-    /// Parse a property name or keyword.
-    fn parse_identifier(&mut self) -> String {
-        self.consume_while(valid_identifier_char)
+    /// Read the current token without consuming it.
+    fn next_token(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
     }
+}
 
-    /// Consume and discard zero or more whitespace characters.
-    fn consume_whitespace(&mut self) {
-        self.consume_while(char::is_whitespace);
+/// Interpret the tokens of an `an+b` expression, with whitespace removed, such as `odd`, `3`,
+/// `-n+2` or `2n - 1`.
+fn parse_nth(tokens: &[Token]) -> Option<Nth> {
+    // Whitespace is allowed around the parts of `an+b`, except after a leading `+`.
+    let is_space = |token: &Token| *token == Token::Whitespace;
+    let start = tokens.iter().position(|token| !is_space(token))?;
+    let end = tokens.iter().rposition(|token| !is_space(token))? + 1;
+    let tokens = &tokens[start..end];
+
+    let integer = |token: &Token, sign: Option<bool>| match *token {
+        Token::Number {
+            value,
+            is_integer: true,
+            signed,
+        } if sign.is_none_or(|sign| sign == signed) => Some(clamp_to_i32(value)),
+        _ => None,
+    };
+
+    // Find `a` and whatever follows the `n` in the same token.
+    let (a, after_n, rest) = match tokens {
+        [Token::Ident(keyword)] if keyword.eq_ignore_ascii_case("odd") => {
+            return Some(Nth { a: 2, b: 1 })
+        }
+        [Token::Ident(keyword)] if keyword.eq_ignore_ascii_case("even") => {
+            return Some(Nth { a: 2, b: 0 })
+        }
+        [Token::Dimension {
+            value,
+            is_integer: true,
+            unit,
+        }, rest @ ..] => (clamp_to_i32(*value), unit.as_str(), rest),
+        [Token::Delim('+'), Token::Ident(name), rest @ ..] => (1, name.as_str(), rest),
+        [Token::Ident(name), rest @ ..] => match name.strip_prefix('-') {
+            Some(name) => (-1, name, rest),
+            None => (1, name.as_str(), rest),
+        },
+        [number] => return integer(number, None).map(|b| Nth { a: 0, b }),
+        _ => return None,
+    };
+    let after_n = match after_n.get(..1) {
+        Some(n) if n.eq_ignore_ascii_case("n") => &after_n[1..],
+        _ => return None,
+    };
+
+    // A sign on its own must be followed by an integer without one.
+    let rest: Vec<&Token> = rest.iter().filter(|token| !is_space(token)).collect();
+    let b = match (after_n, &rest[..]) {
+        ("", []) => 0,
+        ("", [number]) => integer(number, Some(true))?,
+        ("", [Token::Delim('+'), number]) => integer(number, Some(false))?,
+        ("", [Token::Delim('-'), number]) => integer(number, Some(false))?.saturating_neg(),
+        ("-", [number]) => integer(number, Some(false))?.saturating_neg(),
+        (digits, []) if digits.len() > 1 && digits.starts_with('-') => {
+            if !digits[1..].bytes().all(|byte| byte.is_ascii_digit()) {
+                return None;
+            }
+            clamp_to_i32(digits.parse().ok()?)
+        }
+        _ => return None,
+    };
+    Some(Nth { a, b })
+}
+
+/// Convert a CSS integer to `i32`, clamping it to the range that `i32` can hold.
+fn clamp_to_i32(value: f32) -> i32 {
+    value.clamp(i32::MIN as f32, i32::MAX as f32) as i32
+}
+
+// Methods for parsing values:
+
+/// Interpret the component values of a declaration, or return `None` if this engine doesn't
+/// support them.
+fn parse_value(components: &[ComponentValue]) -> Option<Value> {
+    let [component] = components else {
+        return None;
+    };
+    match component {
+        ComponentValue::Token(token) => match token {
+            Token::Ident(keyword) => Some(Value::Keyword(keyword.clone())),
+            Token::Dimension { value, unit, .. } => Some(Value::Length(*value, parse_unit(unit)?)),
+            // Zero lengths don't need a unit.
+            Token::Number { value, .. } if *value == 0.0 => Some(Value::Length(0.0, Unit::Px)),
+            Token::Number { value, .. } => Some(Value::Number(*value)),
+            Token::String(value) => Some(Value::Str(value.clone())),
+            Token::Hash { value, .. } => parse_hex_color(value).map(Value::ColorValue),
+            _ => None,
+        },
+        ComponentValue::Function(name, args)
+            if name.eq_ignore_ascii_case("rgb") || name.eq_ignore_ascii_case("rgba") =>
+        {
+            parse_rgb(args).map(Value::ColorValue)
+        }
+        _ => None,
     }
+}
 
-    /// Consume characters until `test` returns false.
-    fn consume_while<F>(&mut self, test: F) -> String
-    where
-        F: Fn(char) -> bool,
-    {
-        let mut result = String::new();
-        while !self.eof() && test(self.next_char()) {
-            result.push(self.consume_char())
+/// Split component values at whitespace, into the items of a space-separated list.
+fn split_at_whitespace(components: &[ComponentValue]) -> Vec<&ComponentValue> {
+    components
+        .iter()
+        .filter(|component| **component != ComponentValue::Token(Token::Whitespace))
+        .collect()
+}
+
+/// Interpret the value of the `content` property: `none`, `normal`, or a list of strings,
+/// `attr()`, `counter()` and `counters()`.
+fn parse_content(components: &[ComponentValue]) -> Option<Value> {
+    let identifier = |component: &ComponentValue| match component {
+        ComponentValue::Token(Token::Ident(name)) => Some(name.clone()),
+        _ => None,
+    };
+    let items = split_at_whitespace(components);
+    if let [ComponentValue::Token(Token::Ident(keyword))] = items[..] {
+        return match &*keyword.to_ascii_lowercase() {
+            "none" | "normal" => Some(Value::Keyword(keyword.to_ascii_lowercase())),
+            _ => None,
+        };
+    }
+
+    let mut values = vec![];
+    for item in items {
+        values.push(match item {
+            ComponentValue::Token(Token::String(value)) => Value::Str(value.clone()),
+            ComponentValue::Function(name, args) => {
+                let args = split_at_whitespace(args);
+                match (&*name.to_ascii_lowercase(), &args[..]) {
+                    // HTML attribute names are case-insensitive.
+                    ("attr", [name]) => Value::Attr(identifier(name)?.to_ascii_lowercase()),
+                    ("counter", [name]) => Value::Counter(identifier(name)?),
+                    ("counters", [name, comma, ComponentValue::Token(Token::String(sep))])
+                        if **comma == ComponentValue::Token(Token::Comma) =>
+                    {
+                        Value::Counters(identifier(name)?, sep.clone())
+                    }
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        });
+    }
+    match values.len() {
+        0 => None,
+        1 => values.pop(),
+        _ => Some(Value::List(values)),
+    }
+}
+
+/// Interpret the value of `counter-reset` or `counter-increment`: `none`, or counter names each
+/// optionally followed by an integer, which is `default` if left out. The result is a list of
+/// alternating `Keyword` names and `Number` values.
+fn parse_counter_changes(components: &[ComponentValue], default: i32) -> Option<Value> {
+    let items = split_at_whitespace(components);
+    if let [ComponentValue::Token(Token::Ident(keyword))] = items[..] {
+        if keyword.eq_ignore_ascii_case("none") {
+            return Some(Value::Keyword("none".to_string()));
         }
-        result
     }
 
-    /// Return the current character, and advance self.pos to the next character.
-    fn consume_char(&mut self) -> char {
-        let mut iter = self.input[self.pos..].char_indices();
-        let (_, cur_char) = iter.next().unwrap();
-        let (next_pos, _) = iter.next().unwrap_or((1, ' '));
-        self.pos += next_pos;
-        cur_char
+    let mut values = vec![];
+    let mut items = items.into_iter().peekable();
+    while let Some(item) = items.next() {
+        match item {
+            ComponentValue::Token(Token::Ident(name)) if !name.eq_ignore_ascii_case("none") => {
+                values.push(Value::Keyword(name.clone()));
+            }
+            _ => return None,
+        }
+        let value = match items.peek() {
+            Some(ComponentValue::Token(Token::Number {
+                value,
+                is_integer: true,
+                ..
+            })) => {
+                items.next();
+                clamp_to_i32(*value)
+            }
+            _ => default,
+        };
+        values.push(Value::Number(value as f32));
+    }
+    if values.is_empty() {
+        return None;
+    }
+    Some(Value::List(values))
+}
+
+fn parse_unit(unit: &str) -> Option<Unit> {
+    match &*unit.to_ascii_lowercase() {
+        "px" => Some(Unit::Px),
+        _ => None,
+    }
+}
+
+/// Parse the digits of a color in `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa` notation.
+fn parse_hex_color(digits: &str) -> Option<Color> {
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |s: &str| u8::from_str_radix(s, 16).unwrap();
+    // In the short forms each digit is repeated, so `#fa0` is `#ffaa00`.
+    let short = |i: usize| channel(&digits[i..i + 1]) * 17;
+    let long = |i: usize| channel(&digits[2 * i..2 * i + 2]);
+    let (r, g, b, a) = match digits.len() {
+        3 => (short(0), short(1), short(2), 255),
+        4 => (short(0), short(1), short(2), short(3)),
+        6 => (long(0), long(1), long(2), 255),
+        8 => (long(0), long(1), long(2), long(3)),
+        _ => return None,
+    };
+    Some(Color { r, g, b, a })
+}
+
+/// Parse the arguments of `rgb()` or `rgba()`: either `r, g, b[, a]` or `r g b[ / a]`.
+fn parse_rgb(args: &[ComponentValue]) -> Option<Color> {
+    let args: Vec<&ComponentValue> = args
+        .iter()
+        .filter(|arg| **arg != ComponentValue::Token(Token::Whitespace))
+        .collect();
+    let separator = match args.get(1) {
+        Some(ComponentValue::Token(Token::Comma)) => Token::Comma,
+        _ => Token::Delim('/'),
+    };
+
+    // Every other argument is a separator, except between the color channels in the
+    // space-separated form.
+    let mut channels = vec![];
+    let mut rest = &args[..];
+    while let Some((first, tail)) = rest.split_first() {
+        let ComponentValue::Token(token) = first else {
+            return None;
+        };
+        channels.push(token);
+        rest = tail;
+        let expects_separator = separator == Token::Comma || channels.len() == 3;
+        if expects_separator && !rest.is_empty() {
+            if *rest[0] != ComponentValue::Token(separator.clone()) {
+                return None;
+            }
+            rest = &rest[1..];
+        }
     }
 
-    /// Read the current character without consuming it.
-    fn next_char(&self) -> char {
-        self.input[self.pos..].chars().next().unwrap()
+    let channel = |token: &Token| match *token {
+        Token::Number { value, .. } => Some(value.clamp(0.0, 255.0).round() as u8),
+        Token::Percentage(value) => Some((value.clamp(0.0, 100.0) * 2.55).round() as u8),
+        _ => None,
+    };
+    let alpha = |token: &Token| match *token {
+        Token::Number { value, .. } => Some((value.clamp(0.0, 1.0) * 255.0).round() as u8),
+        Token::Percentage(value) => Some((value.clamp(0.0, 100.0) * 2.55).round() as u8),
+        _ => None,
+    };
+    match channels[..] {
+        [r, g, b] => Some(Color {
+            r: channel(r)?,
+            g: channel(g)?,
+            b: channel(b)?,
+            a: 255,
+        }),
+        [r, g, b, a] => Some(Color {
+            r: channel(r)?,
+            g: channel(g)?,
+            b: channel(b)?,
+            a: alpha(a)?,
+        }),
+        _ => None,
     }
+}
 
-    /// Return true if all input is consumed.
-    fn eof(&self) -> bool {
-        self.pos >= self.input.len()
+/// Drop any whitespace at the end of a list of component values.
+fn trim_trailing_whitespace(components: &mut Vec<ComponentValue>) {
+    while components.last() == Some(&ComponentValue::Token(Token::Whitespace)) {
+        components.pop();
     }
 }
 
-fn valid_identifier_char(c: char) -> bool {
-    match c {
-        'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => true, // TODO: Include U+00A0 and higher.
+/// Remove a trailing `!important` from the value of a declaration, returning whether there was
+/// one. Whitespace is allowed after the `!`.
+fn strip_important(components: &mut Vec<ComponentValue>) -> bool {
+    let Some(ComponentValue::Token(Token::Ident(name))) = components.last() else {
+        return false;
+    };
+    if !name.eq_ignore_ascii_case("important") {
+        return false;
+    }
+    let bang = components[..components.len() - 1]
+        .iter()
+        .rposition(|component| *component != ComponentValue::Token(Token::Whitespace));
+    match bang {
+        Some(bang) if components[bang] == ComponentValue::Token(Token::Delim('!')) => {
+            components.truncate(bang);
+            trim_trailing_whitespace(components);
+            true
+        }
         _ => false,
     }
 }
@@ -307,11 +1854,18 @@ mod tests {
                     class: vec![],
                     id: Some(String::from("foo")),
                     tag_name: None,
+                    attributes: vec![],
+                    pseudo_classes: vec![],
+                    pseudo_element: None,
                 })],
                 declarations: vec![Declaration {
                     name: String::from("display"),
                     value: Value::Keyword(String::from("inline")),
+                    important: false,
+                    span: None,
                 }],
+                span: None,
+                path: None,
             }],
         };
         assert_eq!(expected, parse(source));
@@ -333,11 +1887,18 @@ mod tests {
                     class: vec![String::from("foo")],
                     id: None,
                     tag_name: None,
+                    attributes: vec![],
+                    pseudo_classes: vec![],
+                    pseudo_element: None,
                 })],
                 declarations: vec![Declaration {
                     name: String::from("display"),
                     value: Value::Keyword(String::from("inline")),
+                    important: false,
+                    span: None,
                 }],
+                span: None,
+                path: None,
             }],
         };
         assert_eq!(expected, parse(source));
@@ -360,22 +1921,179 @@ mod tests {
                         class: vec![],
                         id: None,
                         tag_name: Some(String::from("foo")),
+                        attributes: vec![],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
                     }),
                     Selector::Simple(SimpleSelector {
                         class: vec![],
                         id: None,
                         tag_name: Some(String::from("bar")),
+                        attributes: vec![],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
                     }),
                 ],
                 declarations: vec![Declaration {
                     name: String::from("display"),
                     value: Value::Keyword(String::from("inline")),
+                    important: false,
+                    span: None,
                 }],
+                span: None,
+                path: None,
             }],
         };
         assert_eq!(expected, parse(source));
     }
 
+    #[test]
+    fn test_parse_combinators() {
+        let simple = |tag_name: &str, class: &[&str]| SimpleSelector {
+            class: class.iter().map(|c| String::from(*c)).collect(),
+            id: None,
+            tag_name: Some(String::from(tag_name)),
+            attributes: vec![],
+            pseudo_classes: vec![],
+            pseudo_element: None,
+        };
+
+        let selectors = parse_selector_list(String::from("ul > li.a a,h1+p ~ p, div")).unwrap();
+        assert_eq!(
+            vec![
+                Selector::Complex {
+                    subject: simple("a", &[]),
+                    context: vec![
+                        (Combinator::Descendant, simple("li", &["a"])),
+                        (Combinator::Child, simple("ul", &[])),
+                    ],
+                },
+                Selector::Complex {
+                    subject: simple("p", &[]),
+                    context: vec![
+                        (Combinator::SubsequentSibling, simple("p", &[])),
+                        (Combinator::NextSibling, simple("h1", &[])),
+                    ],
+                },
+                Selector::Simple(simple("div", &[])),
+            ],
+            selectors
+        );
+        assert_eq!((0, 1, 3), selectors[0].specificity());
+        assert_eq!((0, 0, 3), selectors[1].specificity());
+
+        assert!(parse_selector_list(String::from("a >")).is_err());
+        assert!(parse_selector_list(String::from("> a")).is_err());
+        let error = parse_selector_list(String::from("a, b {")).unwrap_err();
+        assert_eq!((5, 1, 6), (error.offset, error.line, error.column));
+        assert_eq!(
+            ("',' or end of input", "'{'"),
+            (&error.expected[..], &error.found[..])
+        );
+    }
+
+    #[test]
+    fn test_parse_attribute_selectors() {
+        let selectors = parse_selector_list(String::from(
+            r#"a[HREF], [data-size = "large" i], [lang|=en s]"#,
+        ))
+        .unwrap();
+        let attribute = |name: &str, operator, value: &str, case_insensitive| AttributeSelector {
+            name: String::from(name),
+            operator,
+            value: String::from(value),
+            case_insensitive,
+        };
+        fn attributes(selector: &Selector) -> &[AttributeSelector] {
+            match selector {
+                Selector::Simple(simple) => &simple.attributes,
+                Selector::Complex { .. } => panic!("expected a simple selector"),
+            }
+        }
+
+        // Sorted by specificity, with the type selector making the first one the most specific.
+        assert_eq!((0, 1, 1), selectors[0].specificity());
+        assert_eq!(
+            [attribute("href", None, "", false)],
+            attributes(&selectors[0])
+        );
+        assert_eq!(
+            [attribute(
+                "data-size",
+                Some(AttributeOperator::Equals),
+                "large",
+                true
+            )],
+            attributes(&selectors[1])
+        );
+        assert_eq!(
+            [attribute(
+                "lang",
+                Some(AttributeOperator::DashMatch),
+                "en",
+                false
+            )],
+            attributes(&selectors[2])
+        );
+
+        assert!(parse_selector_list(String::from("[a=]")).is_err());
+        assert!(parse_selector_list(String::from("[a=b x]")).is_err());
+        assert!(parse_selector_list(String::from("[a")).is_err());
+    }
+
+    #[test]
+    fn test_parse_pseudo_classes() {
+        let nth = |source: &str| {
+            let selectors =
+                parse_selector_list(format!(":nth-child({})", source)).unwrap_or_default();
+            match &selectors[..] {
+                [Selector::Simple(simple)] => match simple.pseudo_classes[..] {
+                    [PseudoClass::NthChild(nth, _)] => Some((nth.a, nth.b)),
+                    _ => None,
+                },
+                _ => None,
+            }
+        };
+        assert_eq!(Some((2, 1)), nth("odd"));
+        assert_eq!(Some((2, 0)), nth(" EVEN "));
+        assert_eq!(Some((0, -3)), nth("-3"));
+        assert_eq!(Some((1, 0)), nth("n"));
+        assert_eq!(Some((1, 2)), nth("+n+2"));
+        assert_eq!(Some((-1, 3)), nth("-n+ 3"));
+        assert_eq!(Some((3, -2)), nth("3n-2"));
+        assert_eq!(Some((2, -1)), nth("2n - 1"));
+        assert_eq!(Some((-2, 4)), nth("-2N+4"));
+        assert_eq!(None, nth("2.5n"));
+        assert_eq!(None, nth("n2"));
+        assert_eq!(Some((1, i32::MIN)), nth("n-2147483648"));
+        assert_eq!(Some((-1, i32::MIN)), nth("-n-99999999999"));
+        assert_eq!(
+            Some((i32::MAX, i32::MIN + 1)),
+            nth("9999999999n - 2147483647")
+        );
+        assert_eq!(Some((0, i32::MAX)), nth("3000000000"));
+        assert_eq!(None, nth("-n-1e3"));
+        assert_eq!(Some((2, 1)), nth("2n +1"));
+        assert_eq!(Some((-1, -2)), nth(" -n- 2 "));
+        for invalid in [
+            "2n + -1", "2n+ +1", "+ n", "2n 1", "n- -1", "-n -+1", "2n+-1",
+        ] {
+            assert_eq!(None, nth(invalid), "{}", invalid);
+        }
+
+        let specificity =
+            |source: &str| parse_selector_list(String::from(source)).unwrap()[0].specificity();
+        assert_eq!((0, 1, 1), specificity("li:first-child"));
+        assert_eq!((1, 0, 1), specificity("p:not(.a, #b)"));
+        assert_eq!((0, 1, 1), specificity(":is(p, .a) b"));
+        assert_eq!((0, 0, 0), specificity(":where(#a, .b)"));
+        assert_eq!((0, 2, 0), specificity(":nth-child(2n of .a)"));
+
+        assert!(parse_selector_list(String::from(":hover-ish")).is_err());
+        assert!(parse_selector_list(String::from(":not(p")).is_err());
+        assert!(parse_selector_list(String::from(":nth-of-type(1 of p)")).is_err());
+    }
+
     #[test]
     fn test_parse_multiple_declarations() {
         let source = String::from(
@@ -396,23 +2114,34 @@ mod tests {
                     class: vec![],
                     id: None,
                     tag_name: Some(String::from("html")),
+                    attributes: vec![],
+                    pseudo_classes: vec![],
+                    pseudo_element: None,
                 })],
                 declarations: vec![
                     Declaration {
                         name: String::from("width"),
                         value: Value::Length(600.0, Unit::Px),
+                        important: false,
+                        span: None,
                     },
                     Declaration {
                         name: String::from("padding"),
                         value: Value::Length(10.0, Unit::Px),
+                        important: false,
+                        span: None,
                     },
                     Declaration {
                         name: String::from("border-width"),
                         value: Value::Length(1.0, Unit::Px),
+                        important: false,
+                        span: None,
                     },
                     Declaration {
                         name: String::from("margin"),
                         value: Value::Keyword(String::from("auto")),
+                        important: false,
+                        span: None,
                     },
                     Declaration {
                         name: String::from("background"),
@@ -422,8 +2151,12 @@ mod tests {
                             b: 204,
                             a: 255,
                         }),
+                        important: false,
+                        span: None,
                     },
                 ],
+                span: None,
+                path: None,
             }],
         };
         assert_eq!(expected, parse(source));
@@ -452,22 +2185,33 @@ mod tests {
                             class: vec![],
                             id: None,
                             tag_name: Some(String::from("h1")),
+                            attributes: vec![],
+                            pseudo_classes: vec![],
+                            pseudo_element: None,
                         }),
                         Selector::Simple(SimpleSelector {
                             class: vec![],
                             id: None,
                             tag_name: Some(String::from("h2")),
+                            attributes: vec![],
+                            pseudo_classes: vec![],
+                            pseudo_element: None,
                         }),
                         Selector::Simple(SimpleSelector {
                             class: vec![],
                             id: None,
                             tag_name: Some(String::from("h3")),
+                            attributes: vec![],
+                            pseudo_classes: vec![],
+                            pseudo_element: None,
                         }),
                     ],
                     declarations: vec![
                         Declaration {
                             name: String::from("margin"),
                             value: Value::Keyword(String::from("auto")),
+                            important: false,
+                            span: None,
                         },
                         Declaration {
                             name: String::from("color"),
@@ -477,28 +2221,400 @@ mod tests {
                                 b: 0,
                                 a: 255,
                             }),
+                            important: false,
+                            span: None,
                         },
                     ],
+                    span: None,
+                    path: None,
                 },
                 Rule {
                     selectors: vec![Selector::Simple(SimpleSelector {
                         class: vec![String::from("note")],
                         id: None,
                         tag_name: Some(String::from("div")),
+                        attributes: vec![],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
                     })],
                     declarations: vec![
                         Declaration {
                             name: String::from("margin-bottom"),
                             value: Value::Length(20.0, Unit::Px),
+                            important: false,
+                            span: None,
                         },
                         Declaration {
                             name: String::from("padding"),
                             value: Value::Length(10.0, Unit::Px),
+                            important: false,
+                            span: None,
                         },
                     ],
+                    span: None,
+                    path: None,
                 },
             ],
         };
         assert_eq!(expected, parse(source));
     }
+
+    #[test]
+    fn test_parse_declarations_skips_invalid() {
+        let declarations = parse_declarations(String::from(
+            "color; margin: 2px; font-family: a, b; width: 1em; display: block",
+        ));
+        assert_eq!(
+            vec![
+                Declaration {
+                    name: String::from("margin"),
+                    value: Value::Length(2.0, Unit::Px),
+                    important: false,
+                    span: None,
+                },
+                Declaration {
+                    name: String::from("display"),
+                    value: Value::Keyword(String::from("block")),
+                    important: false,
+                    span: None,
+                },
+            ],
+            declarations
+        );
+    }
+
+    #[test]
+    fn test_parse_imports() {
+        let source = String::from(
+            r#"@charset "utf-8";
+        @import url("base.css");
+        @IMPORT 'print.css' print;
+        p {
+            display: block;
+        }
+        "#,
+        );
+
+        let mut urls = vec![];
+        let stylesheet = parse_with_imports(source, |url| {
+            urls.push(url.to_string());
+            Some(parse(format!(
+                "{} {{ display: none; }}",
+                url.replace(".css", "")
+            )))
+        });
+
+        let rule = |tag_name: &str, display: &str| Rule {
+            selectors: vec![Selector::Simple(SimpleSelector {
+                class: vec![],
+                id: None,
+                tag_name: Some(String::from(tag_name)),
+                attributes: vec![],
+                pseudo_classes: vec![],
+                pseudo_element: None,
+            })],
+            declarations: vec![Declaration {
+                name: String::from("display"),
+                value: Value::Keyword(String::from(display)),
+                important: false,
+                span: None,
+            }],
+            span: None,
+            path: None,
+        };
+        assert_eq!(vec!["base.css", "print.css"], urls);
+        assert_eq!(
+            Stylesheet {
+                rules: vec![
+                    rule("base", "none"),
+                    rule("print", "none"),
+                    rule("p", "block")
+                ],
+            },
+            stylesheet
+        );
+    }
+
+    #[test]
+    fn test_source_spans() {
+        let source = String::from("h1 {\n  margin: auto;\n}\n\n.note { padding: 10px }");
+        let stylesheet = parse(source);
+
+        let first = &stylesheet.rules[0];
+        assert_eq!("1:1", first.span.unwrap().to_string());
+        let end = first.span.unwrap().end;
+        assert_eq!((3, 2), (end.line, end.column));
+        assert_eq!("2:3", first.declarations[0].span.unwrap().to_string());
+
+        let second = &stylesheet.rules[1];
+        assert_eq!("5:1", second.span.unwrap().to_string());
+        assert_eq!("5:9", second.declarations[0].span.unwrap().to_string());
+    }
+
+    #[test]
+    fn test_comments_and_error_recovery() {
+        let source = String::from(
+            r#"/* Header */
+@media print { p { display: none; } }
+p/* a */, div {
+    font-family: "Helvetica", sans-serif; /* unsupported value */
+    margin: 0;
+    color: #f00 !important;
+    padding: 10px
+}
+h1 $ { display: block; }
+em { width: 5em; display: inline ! Important; }
+span { display"#,
+        );
+        let (stylesheet, errors) = parse_with_errors(source, |_| None);
+
+        let rule = |tag_names: &[&str], declarations: Vec<(&str, Value, bool)>| Rule {
+            selectors: tag_names
+                .iter()
+                .map(|tag_name| {
+                    Selector::Simple(SimpleSelector {
+                        class: vec![],
+                        id: None,
+                        tag_name: Some(String::from(*tag_name)),
+                        attributes: vec![],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
+                    })
+                })
+                .collect(),
+            declarations: declarations
+                .into_iter()
+                .map(|(name, value, important)| Declaration {
+                    name: String::from(name),
+                    value,
+                    important,
+                    span: None,
+                })
+                .collect(),
+            span: None,
+            path: None,
+        };
+        assert_eq!(
+            vec![
+                rule(
+                    &["p", "div"],
+                    vec![
+                        ("margin", Value::Length(0.0, Unit::Px), false),
+                        (
+                            "color",
+                            Value::ColorValue(Color {
+                                r: 255,
+                                g: 0,
+                                b: 0,
+                                a: 255
+                            }),
+                            true
+                        ),
+                        ("padding", Value::Length(10.0, Unit::Px), false),
+                    ]
+                ),
+                rule(
+                    &["em"],
+                    vec![("display", Value::Keyword(String::from("inline")), true)]
+                ),
+                rule(&["span"], vec![]),
+            ],
+            stylesheet.rules
+        );
+
+        let errors: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+        assert_eq!(
+            vec![
+                "2:1: expected a style rule, found '@media'",
+                "4:18: expected a supported value, found '\"Helvetica\", sans-serif'",
+                "9:4: expected a selector, found '$'",
+                "10:13: expected a supported value, found '5em'",
+                "11:15: expected ':', found end of input",
+                "11:15: expected '}', found end of input",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let tokens: Vec<Token> = tokenize(
+            "@media url( a.png ) #x1 #1 -5px 1e3px 50% +.5 'q\\'s' /* c */ f(<!-- --> \\31 a",
+        )
+        .into_iter()
+        .map(|(token, _)| token)
+        .collect();
+        assert_eq!(
+            vec![
+                Token::AtKeyword(String::from("media")),
+                Token::Whitespace,
+                Token::Url(String::from("a.png")),
+                Token::Whitespace,
+                Token::Hash {
+                    value: String::from("x1"),
+                    is_id: true
+                },
+                Token::Whitespace,
+                Token::Hash {
+                    value: String::from("1"),
+                    is_id: false
+                },
+                Token::Whitespace,
+                Token::Dimension {
+                    value: -5.0,
+                    is_integer: true,
+                    unit: String::from("px")
+                },
+                Token::Whitespace,
+                Token::Dimension {
+                    value: 1000.0,
+                    is_integer: false,
+                    unit: String::from("px")
+                },
+                Token::Whitespace,
+                Token::Percentage(50.0),
+                Token::Whitespace,
+                Token::Number {
+                    value: 0.5,
+                    is_integer: false,
+                    signed: true
+                },
+                Token::Whitespace,
+                Token::String(String::from("q's")),
+                Token::Whitespace,
+                Token::Whitespace,
+                Token::Function(String::from("f")),
+                Token::Cdo,
+                Token::Whitespace,
+                Token::Cdc,
+                Token::Whitespace,
+                Token::Ident(String::from("1a")),
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn test_parse_values() {
+        let value = |source: &str| {
+            let declarations = parse_declarations(format!("x: {}", source));
+            declarations.into_iter().next().map(|d| d.value)
+        };
+        let color = |r, g, b, a| Some(Value::ColorValue(Color { r, g, b, a }));
+
+        assert_eq!(Some(Value::Length(-5.0, Unit::Px)), value("-5px"));
+        assert_eq!(Some(Value::Length(1000.0, Unit::Px)), value("1e3PX"));
+        assert_eq!(Some(Value::Number(1.5)), value("1.5"));
+        assert_eq!(
+            Some(Value::Str(String::from("a \"b\""))),
+            value(r#""a \"b\"""#)
+        );
+        assert_eq!(color(1, 2, 3, 255), value("rgb(1, 2, 3)"));
+        assert_eq!(color(255, 0, 128, 128), value("rgba(100%, 0, 50%, 0.5)"));
+        assert_eq!(color(1, 2, 3, 0), value("rgb(1 2 3 / 0)"));
+        assert_eq!(color(255, 170, 0, 255), value("#fa0"));
+        assert_eq!(None, value("rgb(1, 2)"));
+        assert_eq!(None, value("1 2"));
+
+        assert_eq!(
+            vec![
+                ComponentValue::Function(
+                    String::from("calc"),
+                    vec![
+                        ComponentValue::Token(Token::Dimension {
+                            value: 1.0,
+                            is_integer: true,
+                            unit: String::from("px"),
+                        }),
+                        ComponentValue::Token(Token::Whitespace),
+                        ComponentValue::Token(Token::Delim('+')),
+                        ComponentValue::Token(Token::Whitespace),
+                        ComponentValue::Block(
+                            Token::OpenParen,
+                            vec![ComponentValue::Token(Token::Number {
+                                value: 2.0,
+                                is_integer: true,
+                                signed: false,
+                            })]
+                        ),
+                    ]
+                ),
+                ComponentValue::Token(Token::Ident(String::from("a"))),
+            ],
+            parse_component_values("calc(1px + (2))a")
+        );
+    }
+
+    #[test]
+    fn test_parse_generated_content() {
+        let pseudo_element =
+            |source: &str| match &parse_selector_list(String::from(source)).unwrap()[..] {
+                [Selector::Simple(simple)] => simple.pseudo_element,
+                [Selector::Complex { subject, .. }] => subject.pseudo_element,
+                _ => panic!("invalid selector {}", source),
+            };
+        assert_eq!(Some(PseudoElement::Before), pseudo_element("p::before"));
+        assert_eq!(Some(PseudoElement::After), pseudo_element("ul > li:AFTER"));
+        assert_eq!(None, pseudo_element("a:hover"));
+        assert_eq!(
+            (0, 0, 2),
+            parse_selector_list(String::from("p::after")).unwrap()[0].specificity()
+        );
+        for invalid in [
+            "p::first-line",
+            "p::before span",
+            "p::before.x",
+            "p::before:hover",
+        ] {
+            assert!(
+                parse_selector_list(String::from(invalid)).is_err(),
+                "{}",
+                invalid
+            );
+        }
+
+        let (stylesheet, errors) = parse_with_errors(
+            String::from(
+                r#"p::before {
+                    content: "[" attr(href) counter(a) counters(b, "-") "]";
+                    counter-reset: a b 3;
+                    counter-increment: a -1;
+                }
+                p::after { content: none; counter-reset: none; content: 1px; }"#,
+            ),
+            |_| None,
+        );
+        let values: Vec<&Value> = stylesheet
+            .rules
+            .iter()
+            .flat_map(|rule| rule.declarations.iter().map(|d| &d.value))
+            .collect();
+        let keyword = |s: &str| Value::Keyword(String::from(s));
+        assert_eq!(
+            vec![
+                &Value::List(vec![
+                    Value::Str(String::from("[")),
+                    Value::Attr(String::from("href")),
+                    Value::Counter(String::from("a")),
+                    Value::Counters(String::from("b"), String::from("-")),
+                    Value::Str(String::from("]")),
+                ]),
+                &Value::List(vec![
+                    keyword("a"),
+                    Value::Number(0.0),
+                    keyword("b"),
+                    Value::Number(3.0),
+                ]),
+                &Value::List(vec![keyword("a"), Value::Number(-1.0)]),
+                &keyword("none"),
+                &keyword("none"),
+            ],
+            values
+        );
+        assert_eq!(1, errors.len());
+        assert_eq!(
+            ("a supported value", "'1px'"),
+            (&*errors[0].expected, &*errors[0].found)
+        );
+    }
 }
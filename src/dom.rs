@@ -1,18 +1,49 @@
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem;
+use std::ops::Index;
 
-#[derive(Debug, PartialEq)]
+use crate::source::Span;
+
+/// Identifies a node within its `Document`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+#[derive(Debug)]
 pub struct Node {
-    // data common to all nodes:
-    pub children: Vec<Node>,
+    // links to neighbouring nodes, kept consistent by `Document`:
+    parent: Option<NodeId>,
+    prev_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+
+    // where the node came from, if it was parsed from source text:
+    pub span: Option<Span>,
 
     // data specific to each node type:
     pub node_type: NodeType,
 }
 
+impl Node {
+    fn new(node_type: NodeType) -> Node {
+        Node {
+            parent: None,
+            prev_sibling: None,
+            next_sibling: None,
+            first_child: None,
+            last_child: None,
+            span: None,
+            node_type,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum NodeType {
     Text(String),
     Element(ElementData),
+    Comment(String),
 }
 
 #[derive(Debug, PartialEq)]
@@ -36,21 +67,736 @@ impl ElementData {
 
 pub type AttrMap = HashMap<String, String>;
 
-// Constructor functions for convenience:
+/// A tree of nodes stored in an arena and addressed by `NodeId`, plus document-level
+/// information. The root is normally the `<html>` element, but trees built with the constructor
+/// functions below can have any node at the root.
+#[derive(Debug)]
+pub struct Document {
+    nodes: Vec<Node>,
+    root: NodeId,
+    pub doctype: Option<Doctype>,
+    pub mode: QuirksMode,
+    // Changes made since the records were last taken.
+    records: Vec<MutationRecord>,
+}
+
+/// The contents of a `<!DOCTYPE>` declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Doctype {
+    pub name: String,
+    pub public_id: Option<String>,
+    pub system_id: Option<String>,
+}
+
+/// A change made to a document, like the records a DOM `MutationObserver` receives. Building a
+/// tree with the parser or the constructor functions below doesn't produce any.
+/// https://dom.spec.whatwg.org/#interface-mutationrecord
+#[derive(Debug, Clone, PartialEq)]
+pub enum MutationRecord {
+    /// Children were added to or removed from `target`.
+    ChildList {
+        target: NodeId,
+        added: Vec<NodeId>,
+        removed: Vec<NodeId>,
+        /// The siblings on either side of the added or removed children.
+        previous_sibling: Option<NodeId>,
+        next_sibling: Option<NodeId>,
+    },
+    /// An attribute of the element `target` was set or removed.
+    Attribute {
+        target: NodeId,
+        name: String,
+        old_value: Option<String>,
+    },
+    /// The data of the text or comment node `target` changed.
+    CharacterData { target: NodeId, old_value: String },
+}
+
+/// Which rendering mode a document's doctype selects.
+/// https://dom.spec.whatwg.org/#concept-document-mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuirksMode {
+    NoQuirks,
+    LimitedQuirks,
+    Quirks,
+}
+
+impl Document {
+    /// Create a document whose tree is a single root node.
+    pub fn new(root: NodeType) -> Document {
+        Document {
+            nodes: vec![Node::new(root)],
+            root: NodeId(0),
+            doctype: None,
+            mode: QuirksMode::NoQuirks,
+            records: vec![],
+        }
+    }
+
+    /// The root of the tree.
+    pub fn root(&self) -> NodeRef<'_> {
+        self.get(self.root)
+    }
+
+    /// A handle for navigating from the node `id`.
+    pub fn get(&self, id: NodeId) -> NodeRef<'_> {
+        NodeRef { document: self, id }
+    }
+
+    /// Create a node that isn't attached to the tree yet.
+    pub fn create_node(&mut self, node_type: NodeType) -> NodeId {
+        self.nodes.push(Node::new(node_type));
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Insert `child` as the last child of `parent`. If `child` is already in the tree, it is
+    /// moved.
+    pub fn append_child(&mut self, parent: NodeId, child: NodeId) {
+        self.insert_before(parent, child, None);
+    }
+
+    /// Insert `child` into `parent` before `reference`, or as the last child if `reference` is
+    /// `None`. If `child` is already in the tree, it is moved.
+    ///
+    /// Panics if `reference` isn't a child of `parent`, or if `child` is `parent` or one of its
+    /// ancestors.
+    pub fn insert_before(&mut self, parent: NodeId, child: NodeId, reference: Option<NodeId>) {
+        if let Some(reference) = reference {
+            assert_eq!(
+                Some(parent),
+                self[reference].parent,
+                "not a child of the parent"
+            );
+        }
+        assert!(
+            !self.is_inclusive_ancestor(child, parent),
+            "can't insert a node into itself"
+        );
+
+        // Inserting a node before itself leaves it where it is.
+        let reference = match reference {
+            Some(reference) if reference == child => self[child].next_sibling,
+            reference => reference,
+        };
+        if let Some(old_parent) = self[child].parent {
+            self.remove_child(old_parent, child);
+        }
+        self.link(parent, child, reference);
+        self.records.push(MutationRecord::ChildList {
+            target: parent,
+            added: vec![child],
+            removed: vec![],
+            previous_sibling: self[child].prev_sibling,
+            next_sibling: reference,
+        });
+    }
+
+    /// Detach `child` from `parent`. It keeps its id and descendants, and can be inserted again.
+    ///
+    /// Panics if `child` isn't a child of `parent`.
+    pub fn remove_child(&mut self, parent: NodeId, child: NodeId) {
+        assert_eq!(
+            Some(parent),
+            self[child].parent,
+            "not a child of the parent"
+        );
+        let previous_sibling = self[child].prev_sibling;
+        let next_sibling = self[child].next_sibling;
+        self.unlink(child);
+        self.records.push(MutationRecord::ChildList {
+            target: parent,
+            added: vec![],
+            removed: vec![child],
+            previous_sibling,
+            next_sibling,
+        });
+    }
+
+    /// Put `new_child` in the place of `old_child`, a child of `parent`. If `new_child` is
+    /// already in the tree, it is moved.
+    ///
+    /// Panics if `old_child` isn't a child of `parent`, or if `new_child` is `parent` or one of
+    /// its ancestors.
+    pub fn replace_child(&mut self, parent: NodeId, new_child: NodeId, old_child: NodeId) {
+        assert_eq!(
+            Some(parent),
+            self[old_child].parent,
+            "not a child of the parent"
+        );
+        assert!(
+            !self.is_inclusive_ancestor(new_child, parent),
+            "can't insert a node into itself"
+        );
+        if new_child == old_child {
+            return;
+        }
+
+        let mut reference = self[old_child].next_sibling;
+        if reference == Some(new_child) {
+            reference = self[new_child].next_sibling;
+        }
+        if let Some(old_parent) = self[new_child].parent {
+            self.remove_child(old_parent, new_child);
+        }
+        let previous_sibling = self[old_child].prev_sibling;
+        self.unlink(old_child);
+        self.link(parent, new_child, reference);
+        self.records.push(MutationRecord::ChildList {
+            target: parent,
+            added: vec![new_child],
+            removed: vec![old_child],
+            previous_sibling,
+            next_sibling: reference,
+        });
+    }
+
+    /// Set an attribute of the element `id`. Attribute names are case-insensitive.
+    ///
+    /// Panics if `id` isn't an element.
+    pub fn set_attribute(&mut self, id: NodeId, name: &str, value: String) {
+        let name = name.to_ascii_lowercase();
+        let old_value = self.element_mut(id).attributes.insert(name.clone(), value);
+        self.records.push(MutationRecord::Attribute {
+            target: id,
+            name,
+            old_value,
+        });
+    }
+
+    /// Remove an attribute of the element `id`, if it has one.
+    ///
+    /// Panics if `id` isn't an element.
+    pub fn remove_attribute(&mut self, id: NodeId, name: &str) {
+        let name = name.to_ascii_lowercase();
+        if let Some(old_value) = self.element_mut(id).attributes.remove(&name) {
+            self.records.push(MutationRecord::Attribute {
+                target: id,
+                name,
+                old_value: Some(old_value),
+            });
+        }
+    }
+
+    /// Replace the data of the text or comment node `id`.
+    ///
+    /// Panics if `id` is an element.
+    pub fn set_text(&mut self, id: NodeId, data: String) {
+        let old_value = match self.node_mut(id).node_type {
+            NodeType::Text(ref mut text) | NodeType::Comment(ref mut text) => {
+                mem::replace(text, data)
+            }
+            NodeType::Element(_) => panic!("not a text or comment node"),
+        };
+        self.records.push(MutationRecord::CharacterData {
+            target: id,
+            old_value,
+        });
+    }
 
-pub fn text(data: String) -> Node {
-    Node {
-        children: vec![],
-        node_type: NodeType::Text(data),
+    /// Direct access to a node, for changes that aren't recorded: those made while building a
+    /// tree, before anything can observe it, and the link updates done by the methods above.
+    pub(crate) fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    /// Return the changes made since this was last called, oldest first.
+    pub fn take_records(&mut self) -> Vec<MutationRecord> {
+        mem::take(&mut self.records)
+    }
+
+    // Attach the detached node `child` to `parent`, before `reference` or at the end.
+    fn link(&mut self, parent: NodeId, child: NodeId, reference: Option<NodeId>) {
+        let prev_sibling = match reference {
+            Some(reference) => self[reference].prev_sibling,
+            None => self[parent].last_child,
+        };
+        match prev_sibling {
+            Some(prev_sibling) => self.node_mut(prev_sibling).next_sibling = Some(child),
+            None => self.node_mut(parent).first_child = Some(child),
+        }
+        match reference {
+            Some(reference) => self.node_mut(reference).prev_sibling = Some(child),
+            None => self.node_mut(parent).last_child = Some(child),
+        }
+        let node = self.node_mut(child);
+        node.parent = Some(parent);
+        node.prev_sibling = prev_sibling;
+        node.next_sibling = reference;
+    }
+
+    // Detach `child` from its parent, joining up the siblings on either side.
+    fn unlink(&mut self, child: NodeId) {
+        let node = self.node_mut(child);
+        let parent = node.parent.take().unwrap();
+        let prev_sibling = node.prev_sibling.take();
+        let next_sibling = node.next_sibling.take();
+        match prev_sibling {
+            Some(prev_sibling) => self.node_mut(prev_sibling).next_sibling = next_sibling,
+            None => self.node_mut(parent).first_child = next_sibling,
+        }
+        match next_sibling {
+            Some(next_sibling) => self.node_mut(next_sibling).prev_sibling = prev_sibling,
+            None => self.node_mut(parent).last_child = prev_sibling,
+        }
+    }
+
+    fn is_inclusive_ancestor(&self, ancestor: NodeId, node: NodeId) -> bool {
+        let mut current = Some(node);
+        while let Some(id) = current {
+            if id == ancestor {
+                return true;
+            }
+            current = self[id].parent;
+        }
+        false
+    }
+
+    fn element_mut(&mut self, id: NodeId) -> &mut ElementData {
+        match self.node_mut(id).node_type {
+            NodeType::Element(ref mut elem) => elem,
+            _ => panic!("not an element"),
+        }
     }
+
+    /// Move the tree of `other` into this document, appending its root to `parent`. Returns
+    /// the new id of that root.
+    pub fn append_document(&mut self, parent: NodeId, other: Document) -> NodeId {
+        let offset = self.nodes.len();
+        let shift = |id: Option<NodeId>| id.map(|NodeId(i)| NodeId(i + offset));
+        self.nodes.extend(other.nodes.into_iter().map(|node| Node {
+            parent: shift(node.parent),
+            prev_sibling: shift(node.prev_sibling),
+            next_sibling: shift(node.next_sibling),
+            first_child: shift(node.first_child),
+            last_child: shift(node.last_child),
+            ..node
+        }));
+        let root = NodeId(other.root.0 + offset);
+        self.append_child(parent, root);
+        root
+    }
+}
+
+// Nodes can only be changed through the methods above, so that every change made after the tree
+// is built shows up in `take_records`.
+impl Index<NodeId> for Document {
+    type Output = Node;
+
+    fn index(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+}
+
+/// A node together with the document it belongs to, for walking the tree in any direction.
+#[derive(Clone, Copy)]
+pub struct NodeRef<'a> {
+    document: &'a Document,
+    id: NodeId,
 }
 
-pub fn elem(name: String, attrs: AttrMap, children: Vec<Node>) -> Node {
-    Node {
-        children,
-        node_type: NodeType::Element(ElementData {
-            tag_name: name,
-            attributes: attrs,
-        }),
+impl<'a> NodeRef<'a> {
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn document(&self) -> &'a Document {
+        self.document
+    }
+
+    pub fn node_type(&self) -> &'a NodeType {
+        &self.document[self.id].node_type
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.document[self.id].span
+    }
+
+    /// The element data, if this node is an element.
+    pub fn as_element(&self) -> Option<&'a ElementData> {
+        match self.node_type() {
+            NodeType::Element(elem) => Some(elem),
+            _ => None,
+        }
+    }
+
+    pub fn parent(&self) -> Option<NodeRef<'a>> {
+        self.link(self.document[self.id].parent)
+    }
+
+    pub fn prev_sibling(&self) -> Option<NodeRef<'a>> {
+        self.link(self.document[self.id].prev_sibling)
+    }
+
+    pub fn next_sibling(&self) -> Option<NodeRef<'a>> {
+        self.link(self.document[self.id].next_sibling)
+    }
+
+    pub fn first_child(&self) -> Option<NodeRef<'a>> {
+        self.link(self.document[self.id].first_child)
+    }
+
+    pub fn last_child(&self) -> Option<NodeRef<'a>> {
+        self.link(self.document[self.id].last_child)
+    }
+
+    /// Iterate over the children of this node, first to last.
+    pub fn children(&self) -> Children<'a> {
+        Children {
+            next: self.first_child(),
+        }
+    }
+
+    /// Iterate over this node and its descendants depth-first, visiting each node before its
+    /// children.
+    pub fn pre_order(&self) -> PreOrder<'a> {
+        PreOrder {
+            root: self.id,
+            next: Some(*self),
+        }
+    }
+
+    /// Iterate over this node and its descendants depth-first, visiting each node after its
+    /// children.
+    pub fn post_order(&self) -> PostOrder<'a> {
+        PostOrder {
+            root: self.id,
+            next: Some(self.first_leaf()),
+        }
+    }
+
+    /// The concatenated data of all the text nodes below this one, in document order.
+    pub fn text_content(&self) -> String {
+        self.pre_order()
+            .filter_map(|node| match node.node_type() {
+                NodeType::Text(text) => Some(&text[..]),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn link(&self, id: Option<NodeId>) -> Option<NodeRef<'a>> {
+        id.map(|id| self.document.get(id))
+    }
+
+    // The first node in post-order below this one, found by following first children down.
+    fn first_leaf(&self) -> NodeRef<'a> {
+        let mut node = *self;
+        while let Some(child) = node.first_child() {
+            node = child;
+        }
+        node
+    }
+}
+
+// Nodes are equal if their subtrees have the same contents, even in different documents.
+// Source locations are metadata, so they don't take part in comparisons. This lets parsed trees
+// be compared with trees built by hand.
+impl<'a, 'b> PartialEq<NodeRef<'b>> for NodeRef<'a> {
+    fn eq(&self, other: &NodeRef<'b>) -> bool {
+        self.node_type() == other.node_type() && self.children().eq(other.children())
+    }
+}
+
+impl<'a> fmt::Debug for NodeRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Node")
+            .field("node_type", self.node_type())
+            .field("children", &self.children().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Iterator over the children of a node.
+pub struct Children<'a> {
+    next: Option<NodeRef<'a>>,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = NodeRef<'a>;
+
+    fn next(&mut self) -> Option<NodeRef<'a>> {
+        let node = self.next?;
+        self.next = node.next_sibling();
+        Some(node)
+    }
+}
+
+/// Pre-order iterator over a subtree.
+pub struct PreOrder<'a> {
+    root: NodeId,
+    next: Option<NodeRef<'a>>,
+}
+
+impl<'a> Iterator for PreOrder<'a> {
+    type Item = NodeRef<'a>;
+
+    fn next(&mut self) -> Option<NodeRef<'a>> {
+        let node = self.next?;
+        self.next = node.first_child();
+
+        // With no children, move on to the next sibling of the nearest node that has one,
+        // without leaving the subtree.
+        let mut current = node;
+        while self.next.is_none() && current.id != self.root {
+            self.next = current.next_sibling();
+            current = current.parent().unwrap();
+        }
+        Some(node)
+    }
+}
+
+/// Post-order iterator over a subtree.
+pub struct PostOrder<'a> {
+    root: NodeId,
+    next: Option<NodeRef<'a>>,
+}
+
+impl<'a> Iterator for PostOrder<'a> {
+    type Item = NodeRef<'a>;
+
+    fn next(&mut self) -> Option<NodeRef<'a>> {
+        let node = self.next?;
+        self.next = if node.id == self.root {
+            None
+        } else {
+            match node.next_sibling() {
+                Some(sibling) => Some(sibling.first_leaf()),
+                None => node.parent(),
+            }
+        };
+        Some(node)
+    }
+}
+
+// Constructor functions for convenience. Each returns a document whose root is the new node.
+
+pub fn text(data: String) -> Document {
+    Document::new(NodeType::Text(data))
+}
+
+pub fn comment(data: String) -> Document {
+    Document::new(NodeType::Comment(data))
+}
+
+pub fn elem(name: String, attrs: AttrMap, children: Vec<Document>) -> Document {
+    let mut document = Document::new(NodeType::Element(ElementData {
+        tag_name: name,
+        attributes: attrs,
+    }));
+    let root = document.root;
+    for child in children {
+        document.append_document(root, child);
+    }
+    document.records.clear();
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{comment, elem, text, MutationRecord, NodeRef, NodeType};
+
+    #[test]
+    fn test_navigation() {
+        let document = elem(
+            String::from("ul"),
+            HashMap::new(),
+            vec![
+                elem(
+                    String::from("li"),
+                    HashMap::new(),
+                    vec![text(String::from("a"))],
+                ),
+                elem(String::from("li"), HashMap::new(), vec![]),
+                text(String::from("b")),
+            ],
+        );
+
+        let root = document.root();
+        assert_eq!(None, root.parent().map(|node| node.id()));
+
+        let first = root.first_child().unwrap();
+        let last = root.last_child().unwrap();
+        assert_eq!(&NodeType::Text(String::from("b")), last.node_type());
+        assert_eq!(None, first.prev_sibling().map(|node| node.id()));
+        assert_eq!(None, last.next_sibling().map(|node| node.id()));
+
+        let middle = first.next_sibling().unwrap();
+        assert_eq!(
+            Some(first.id()),
+            middle.prev_sibling().map(|node| node.id())
+        );
+        assert_eq!(Some(last.id()), middle.next_sibling().map(|node| node.id()));
+        assert_eq!(Some(root.id()), middle.parent().map(|node| node.id()));
+
+        let grandchild = first.first_child().unwrap();
+        assert_eq!(
+            Some(root.id()),
+            grandchild
+                .parent()
+                .and_then(|node| node.parent())
+                .map(|node| node.id())
+        );
+        assert_eq!(3, root.children().count());
+    }
+
+    #[test]
+    fn test_mutations() {
+        let mut document = elem(
+            String::from("div"),
+            HashMap::new(),
+            vec![
+                text(String::from("a")),
+                comment(String::from("b")),
+                text(String::from("c")),
+            ],
+        );
+        assert_eq!(Vec::<MutationRecord>::new(), document.take_records());
+
+        let root = document.root().id();
+        let ids: Vec<_> = document.root().children().map(|node| node.id()).collect();
+        let (a, b, c) = (ids[0], ids[1], ids[2]);
+
+        // Moving a node records its removal from the old place, then its insertion.
+        document.insert_before(root, c, Some(a));
+        document.remove_child(root, b);
+        document.set_text(a, String::from("A"));
+        document.set_attribute(root, "ID", String::from("x"));
+        document.remove_attribute(root, "id");
+        document.remove_attribute(root, "missing");
+
+        let expected = elem(
+            String::from("div"),
+            HashMap::new(),
+            vec![text(String::from("c")), text(String::from("A"))],
+        );
+        assert_eq!(expected.root(), document.root());
+        assert_eq!(
+            Some(a),
+            document.get(c).next_sibling().map(|node| node.id())
+        );
+        assert_eq!(Some(a), document.root().last_child().map(|node| node.id()));
+        assert_eq!(None, document.get(b).parent().map(|node| node.id()));
+
+        assert_eq!(
+            vec![
+                MutationRecord::ChildList {
+                    target: root,
+                    added: vec![],
+                    removed: vec![c],
+                    previous_sibling: Some(b),
+                    next_sibling: None,
+                },
+                MutationRecord::ChildList {
+                    target: root,
+                    added: vec![c],
+                    removed: vec![],
+                    previous_sibling: None,
+                    next_sibling: Some(a),
+                },
+                MutationRecord::ChildList {
+                    target: root,
+                    added: vec![],
+                    removed: vec![b],
+                    previous_sibling: Some(a),
+                    next_sibling: None,
+                },
+                MutationRecord::CharacterData {
+                    target: a,
+                    old_value: String::from("a"),
+                },
+                MutationRecord::Attribute {
+                    target: root,
+                    name: String::from("id"),
+                    old_value: None,
+                },
+                MutationRecord::Attribute {
+                    target: root,
+                    name: String::from("id"),
+                    old_value: Some(String::from("x")),
+                },
+            ],
+            document.take_records()
+        );
+        assert!(document.take_records().is_empty());
+    }
+
+    #[test]
+    fn test_replace_child() {
+        let mut document = elem(
+            String::from("p"),
+            HashMap::new(),
+            vec![text(String::from("a")), text(String::from("b"))],
+        );
+        let root = document.root().id();
+        let old = document.root().first_child().unwrap().id();
+        let new = document.create_node(NodeType::Comment(String::from("c")));
+
+        document.replace_child(root, new, old);
+        let expected = elem(
+            String::from("p"),
+            HashMap::new(),
+            vec![comment(String::from("c")), text(String::from("b"))],
+        );
+        assert_eq!(expected.root(), document.root());
+        assert_eq!(1, document.take_records().len());
+
+        // Replacing a node with its next sibling just removes the node.
+        let next = document.get(new).next_sibling().unwrap().id();
+        document.replace_child(root, next, new);
+        let expected = elem(
+            String::from("p"),
+            HashMap::new(),
+            vec![text(String::from("b"))],
+        );
+        assert_eq!(expected.root(), document.root());
+    }
+
+    #[test]
+    fn test_traversal() {
+        let document = elem(
+            String::from("a"),
+            HashMap::new(),
+            vec![
+                elem(
+                    String::from("b"),
+                    HashMap::new(),
+                    vec![text(String::from("1")), comment(String::from("2"))],
+                ),
+                elem(
+                    String::from("c"),
+                    HashMap::new(),
+                    vec![elem(
+                        String::from("d"),
+                        HashMap::new(),
+                        vec![text(String::from("3"))],
+                    )],
+                ),
+            ],
+        );
+        let label = |node: NodeRef| match node.node_type() {
+            NodeType::Element(elem) => elem.tag_name.clone(),
+            NodeType::Text(data) | NodeType::Comment(data) => data.clone(),
+        };
+
+        let root = document.root();
+        let pre_order: Vec<_> = root.pre_order().map(label).collect();
+        assert_eq!(vec!["a", "b", "1", "2", "c", "d", "3"], pre_order);
+        let post_order: Vec<_> = root.post_order().map(label).collect();
+        assert_eq!(vec!["1", "2", "b", "3", "d", "c", "a"], post_order);
+
+        // Traversal stays within the subtree it started from.
+        let b = root.first_child().unwrap();
+        assert_eq!(
+            vec!["b", "1", "2"],
+            b.pre_order().map(label).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["1", "2", "b"],
+            b.post_order().map(label).collect::<Vec<_>>()
+        );
+
+        assert_eq!("13", root.text_content());
     }
 }
@@ -0,0 +1,374 @@
+//! Detecting the character encoding of HTML and CSS source and decoding it to a `String`.
+//!
+//! This follows the Encoding Standard (https://encoding.spec.whatwg.org/) for a few common
+//! encodings. As in browsers, the ISO-8859-1 and ASCII labels decode as windows-1252, which
+//! agrees with them everywhere except for the bytes 0x80 to 0x9F.
+
+/// A supported character encoding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Windows1252,
+}
+
+/// Windows-1252 characters for the bytes 0x80 to 0x9F, which are C1 control codes in
+/// ISO-8859-1. Bytes that windows-1252 leaves undefined map to the control codes.
+pub const WINDOWS_1252_C1: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+// Labels that name each encoding, from https://encoding.spec.whatwg.org/#names-and-labels
+const UTF_8_LABELS: &[&str] = &[
+    "unicode-1-1-utf-8",
+    "unicode11utf8",
+    "unicode20utf8",
+    "utf-8",
+    "utf8",
+    "x-unicode20utf8",
+];
+const UTF_16LE_LABELS: &[&str] = &[
+    "csunicode",
+    "iso-10646-ucs-2",
+    "ucs-2",
+    "unicode",
+    "unicodefeff",
+    "utf-16",
+    "utf-16le",
+];
+const UTF_16BE_LABELS: &[&str] = &["unicodefffe", "utf-16be"];
+const WINDOWS_1252_LABELS: &[&str] = &[
+    "ansi_x3.4-1968",
+    "ascii",
+    "cp1252",
+    "cp819",
+    "csisolatin1",
+    "ibm819",
+    "iso-8859-1",
+    "iso-ir-100",
+    "iso8859-1",
+    "iso88591",
+    "iso_8859-1",
+    "iso_8859-1:1987",
+    "l1",
+    "latin1",
+    "us-ascii",
+    "windows-1252",
+    "x-cp1252",
+];
+
+/// The encoding named by `label`, e.g. `"UTF-8"` or `"latin1"`.
+pub fn for_label(label: &str) -> Option<Encoding> {
+    let label = label
+        .trim_matches(|c: char| c.is_ascii_whitespace())
+        .to_ascii_lowercase();
+    let label = &label[..];
+    if UTF_8_LABELS.contains(&label) {
+        Some(Encoding::Utf8)
+    } else if UTF_16LE_LABELS.contains(&label) {
+        Some(Encoding::Utf16Le)
+    } else if UTF_16BE_LABELS.contains(&label) {
+        Some(Encoding::Utf16Be)
+    } else if WINDOWS_1252_LABELS.contains(&label) {
+        Some(Encoding::Windows1252)
+    } else {
+        None
+    }
+}
+
+/// Decode `bytes` as `encoding`. Malformed sequences become U+FFFD REPLACEMENT CHARACTER.
+pub fn decode(bytes: &[u8], encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+        Encoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+        Encoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+        Encoding::Windows1252 => bytes
+            .iter()
+            .map(|&b| match b {
+                0x80..=0x9F => WINDOWS_1252_C1[(b - 0x80) as usize],
+                _ => b as char,
+            })
+            .collect(),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], to_unit: fn([u8; 2]) -> u16) -> String {
+    let units = bytes.chunks(2).map(|pair| match *pair {
+        [a, b] => to_unit([a, b]),
+        // A trailing odd byte can't be decoded.
+        _ => 0xFFFD,
+    });
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or('\u{FFFD}'))
+        .collect()
+}
+
+/// Detect a byte order mark, returning the encoding it indicates and its length.
+pub fn sniff_bom(bytes: &[u8]) -> Option<(Encoding, usize)> {
+    if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        Some((Encoding::Utf8, 3))
+    } else if bytes.starts_with(&[0xFE, 0xFF]) {
+        Some((Encoding::Utf16Be, 2))
+    } else if bytes.starts_with(&[0xFF, 0xFE]) {
+        Some((Encoding::Utf16Le, 2))
+    } else {
+        None
+    }
+}
+
+/// Decode an HTML document, choosing the encoding from, in order: a byte order mark, a
+/// `<meta charset>` declaration near the start, or the bytes themselves, which are taken to be
+/// UTF-8 if they are valid UTF-8 and windows-1252 otherwise.
+/// https://html.spec.whatwg.org/multipage/parsing.html#determining-the-character-encoding
+pub fn decode_html(bytes: &[u8]) -> (String, Encoding) {
+    if let Some((encoding, len)) = sniff_bom(bytes) {
+        return (decode(&bytes[len..], encoding), encoding);
+    }
+    let encoding = prescan(bytes).unwrap_or_else(|| guess(bytes));
+    (decode(bytes, encoding), encoding)
+}
+
+/// Decode a stylesheet, choosing the encoding from, in order: a byte order mark, an
+/// `@charset` rule at the very start, or the bytes themselves as for `decode_html`.
+/// https://drafts.csswg.org/css-syntax/#input-byte-stream
+pub fn decode_css(bytes: &[u8]) -> (String, Encoding) {
+    if let Some((encoding, len)) = sniff_bom(bytes) {
+        return (decode(&bytes[len..], encoding), encoding);
+    }
+    let encoding = charset_rule(bytes).unwrap_or_else(|| guess(bytes));
+    (decode(bytes, encoding), encoding)
+}
+
+// The encoding to use when the source doesn't declare one.
+fn guess(bytes: &[u8]) -> Encoding {
+    if std::str::from_utf8(bytes).is_ok() {
+        Encoding::Utf8
+    } else {
+        Encoding::Windows1252
+    }
+}
+
+// The encoding named by `@charset "<label>";`, which must be written exactly like that.
+fn charset_rule(bytes: &[u8]) -> Option<Encoding> {
+    let rest = bytes.strip_prefix(b"@charset \"")?;
+    let end = rest.iter().position(|&b| b == b'"')?;
+    if !rest[end..].starts_with(b"\";") {
+        return None;
+    }
+    let label = std::str::from_utf8(&rest[..end]).ok()?;
+    // A stylesheet that claims to be UTF-16 but has ASCII bytes like these can't be.
+    match for_label(label)? {
+        Encoding::Utf16Le | Encoding::Utf16Be => Some(Encoding::Utf8),
+        encoding => Some(encoding),
+    }
+}
+
+// How many bytes the prescan looks at.
+const PRESCAN_LIMIT: usize = 1024;
+
+// Look for a `<meta charset>` or `<meta http-equiv="content-type">` declaration, skipping over
+// comments and other tags.
+// https://html.spec.whatwg.org/multipage/parsing.html#prescan-a-byte-stream-to-determine-its-encoding
+fn prescan(bytes: &[u8]) -> Option<Encoding> {
+    let bytes = &bytes[..bytes.len().min(PRESCAN_LIMIT)];
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let rest = &bytes[pos..];
+        if rest.starts_with(b"<!--") {
+            pos += find(&rest[4..], b"-->").map_or(rest.len(), |i| 4 + i + 3);
+        } else if starts_with_ignore_case(rest, b"<meta")
+            && rest.get(5).is_some_and(|&b| is_space(b) || b == b'/')
+        {
+            pos += 5;
+            if let Some(encoding) = meta_encoding(bytes, &mut pos) {
+                // A document can't declare itself to be UTF-16, since it was read as ASCII.
+                return Some(match encoding {
+                    Encoding::Utf16Le | Encoding::Utf16Be => Encoding::Utf8,
+                    encoding => encoding,
+                });
+            }
+        } else if (rest.starts_with(b"<") && rest.get(1).is_some_and(u8::is_ascii_alphabetic))
+            || (rest.starts_with(b"</") && rest.get(2).is_some_and(u8::is_ascii_alphabetic))
+        {
+            // Skip the tag along with its attributes, which may contain `>` in quotes.
+            pos += rest.iter().position(|&b| is_space(b) || b == b'>')?;
+            while let Some((_, _)) = next_attribute(bytes, &mut pos) {}
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            pos += rest.iter().position(|&b| b == b'>')? + 1;
+        } else {
+            pos += 1;
+        }
+    }
+    None
+}
+
+// Read the attributes of a `<meta>` tag, returning the encoding they declare, if any.
+fn meta_encoding(bytes: &[u8], pos: &mut usize) -> Option<Encoding> {
+    let mut charset = None;
+    let mut content_charset = None;
+    let mut http_equiv_content_type = false;
+    while let Some((name, value)) = next_attribute(bytes, pos) {
+        match &name[..] {
+            "charset" if charset.is_none() => charset = Some(for_label(&value)),
+            "content" if content_charset.is_none() => {
+                content_charset = Some(charset_from_content(&value).and_then(|l| for_label(&l)))
+            }
+            "http-equiv" => http_equiv_content_type = value.eq_ignore_ascii_case("content-type"),
+            _ => {}
+        }
+    }
+    match (charset, content_charset) {
+        (Some(encoding), _) => encoding,
+        (None, Some(encoding)) if http_equiv_content_type => encoding,
+        _ => None,
+    }
+}
+
+// Extract the label from a `content` attribute like `text/html; charset=latin1`.
+// https://html.spec.whatwg.org/multipage/urls-and-fetching.html#algorithm-for-extracting-a-character-encoding-from-a-meta-element
+fn charset_from_content(content: &str) -> Option<String> {
+    let lower = content.to_ascii_lowercase();
+    let start = lower.find("charset")? + "charset".len();
+    let rest = lower[start..].trim_start();
+    let rest = rest.strip_prefix('=')?.trim_start();
+    match rest.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let rest = &rest[1..];
+            rest.find(quote).map(|end| rest[..end].to_string())
+        }
+        _ => Some(
+            rest.split(|c: char| c.is_ascii_whitespace() || c == ';')
+                .next()
+                .unwrap_or("")
+                .to_string(),
+        ),
+    }
+}
+
+// Read one attribute of a tag, with its name lowercased, or `None` at the end of the tag.
+// https://html.spec.whatwg.org/multipage/parsing.html#concept-get-attributes-when-sniffing
+fn next_attribute(bytes: &[u8], pos: &mut usize) -> Option<(String, String)> {
+    while *pos < bytes.len() && (is_space(bytes[*pos]) || bytes[*pos] == b'/') {
+        *pos += 1;
+    }
+    if *pos >= bytes.len() || bytes[*pos] == b'>' {
+        *pos += 1;
+        return None;
+    }
+
+    // A name can start with `=`, but not contain one after that.
+    let mut name = vec![];
+    while let Some(&b) = bytes.get(*pos) {
+        if is_space(b) || b == b'/' || b == b'>' || (b == b'=' && !name.is_empty()) {
+            break;
+        }
+        name.push(b.to_ascii_lowercase());
+        *pos += 1;
+    }
+    while *pos < bytes.len() && is_space(bytes[*pos]) {
+        *pos += 1;
+    }
+    let mut value = vec![];
+    if *pos < bytes.len() && bytes[*pos] == b'=' {
+        *pos += 1;
+        while *pos < bytes.len() && is_space(bytes[*pos]) {
+            *pos += 1;
+        }
+        match bytes.get(*pos) {
+            Some(&quote @ (b'"' | b'\'')) => {
+                *pos += 1;
+                while *pos < bytes.len() && bytes[*pos] != quote {
+                    value.push(bytes[*pos]);
+                    *pos += 1;
+                }
+                *pos += 1;
+            }
+            _ => {
+                while *pos < bytes.len() && !is_space(bytes[*pos]) && bytes[*pos] != b'>' {
+                    value.push(bytes[*pos]);
+                    *pos += 1;
+                }
+            }
+        }
+    }
+    Some((
+        String::from_utf8_lossy(&name).into_owned(),
+        String::from_utf8_lossy(&value).into_owned(),
+    ))
+}
+
+fn is_space(b: u8) -> bool {
+    matches!(b, b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+fn starts_with_ignore_case(bytes: &[u8], prefix: &[u8]) -> bool {
+    bytes.len() >= prefix.len() && bytes[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+fn find(bytes: &[u8], needle: &[u8]) -> Option<usize> {
+    bytes
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_css, decode_html, for_label, Encoding};
+
+    #[test]
+    fn test_decode_html() {
+        // Byte order marks take precedence over everything else.
+        let utf16le = [0xFF, 0xFE, b'<', 0, b'p', 0, b'>', 0, 0xE9, 0];
+        assert_eq!(
+            (String::from("<p>é"), Encoding::Utf16Le),
+            decode_html(&utf16le)
+        );
+        let utf16be = [0xFE, 0xFF, 0, b'a', 0xD8, 0x3D, 0xDE, 0x00];
+        assert_eq!(
+            (String::from("a\u{1F600}"), Encoding::Utf16Be),
+            decode_html(&utf16be)
+        );
+
+        let mut latin1 = b"<!-- <meta charset=utf-8> --><META CHARSET='ISO-8859-1'><p>".to_vec();
+        latin1.extend([0xE9, 0x80]);
+        assert_eq!(
+            (
+                String::from("<!-- <meta charset=utf-8> --><META CHARSET='ISO-8859-1'><p>é€"),
+                Encoding::Windows1252
+            ),
+            decode_html(&latin1)
+        );
+
+        let http_equiv =
+            br#"<html><head title="<meta charset=latin1>"><meta http-equiv="Content-Type" content="text/html; charset=utf-8">"#;
+        assert_eq!(Encoding::Utf8, decode_html(http_equiv).1);
+
+        // Without a declaration, valid UTF-8 is read as UTF-8 and anything else as windows-1252.
+        assert_eq!(
+            (String::from("é"), Encoding::Utf8),
+            decode_html("é".as_bytes())
+        );
+        assert_eq!(
+            (String::from("é"), Encoding::Windows1252),
+            decode_html(&[0xE9])
+        );
+    }
+
+    #[test]
+    fn test_decode_css() {
+        let mut latin1 = b"@charset \"latin1\"; a::before { content: \"".to_vec();
+        latin1.extend([0xA9, b'"', b' ', b'}']);
+        let (source, encoding) = decode_css(&latin1);
+        assert_eq!(Encoding::Windows1252, encoding);
+        assert!(source.ends_with("content: \"\u{A9}\" }"));
+
+        // The rule has to be written exactly like that to count.
+        assert_eq!(Encoding::Utf8, decode_css(b"@charset 'latin1'; a {}").1);
+        assert_eq!(Encoding::Utf8, decode_css(b"@charset \"utf-16\"; a {}").1);
+        assert_eq!(Some(Encoding::Utf16Be), for_label(" UTF-16BE\n"));
+    }
+}
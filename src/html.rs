@@ -1,32 +1,312 @@
 use crate::dom;
+use crate::encoding;
+use crate::entities;
+use crate::source::{LineIndex, Location, Span};
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::fmt;
+use std::mem;
 
-struct Parser {
+/// An error encountered while parsing an HTML document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Byte offset into the source where the error was detected.
+    pub offset: usize,
+    /// 1-based line number of `offset`.
+    pub line: usize,
+    /// 1-based column (in characters) of `offset`.
+    pub column: usize,
+    /// Description of what the parser was expecting.
+    pub expected: String,
+    /// Description of what was actually found.
+    pub found: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: expected {}, found {}",
+            self.line, self.column, self.expected, self.found
+        )
+    }
+}
+
+impl ParseError {
+    fn new(location: Location, expected: &str, found: String) -> ParseError {
+        ParseError {
+            offset: location.offset,
+            line: location.line,
+            column: location.column,
+            expected: expected.to_string(),
+            found,
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Reading a tag fails if it runs into the end of the input. `read_token` recovers from that, so
+// the public functions always succeed.
+type ParseResult<T> = Result<T, ParseError>;
+
+/// A token produced by the `Tokenizer`. Tag and attribute names are ASCII-lowercased, and
+/// character references in text and attribute values are decoded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    StartTag {
+        name: String,
+        attrs: dom::AttrMap,
+        /// Whether the tag ended with `/>`.
+        self_closing: bool,
+    },
+    EndTag {
+        name: String,
+    },
+    Text(String),
+    Comment(String),
+    Doctype {
+        doctype: dom::Doctype,
+        /// Whether the declaration was malformed, which puts the document in quirks mode.
+        force_quirks: bool,
+    },
+}
+
+// How the tokenizer reads the contents of elements like <script> and <title>.
+// https://html.spec.whatwg.org/multipage/parsing.html#tokenization
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RawTextKind {
+    // Contents are kept verbatim.
+    RawText,
+    // Contents are kept verbatim except for character references, which are decoded.
+    Rcdata,
+}
+
+fn raw_text_kind(tag_name: &str) -> Option<RawTextKind> {
+    match tag_name {
+        "script" | "style" | "xmp" | "iframe" | "noembed" | "noframes" => {
+            Some(RawTextKind::RawText)
+        }
+        "textarea" | "title" => Some(RawTextKind::Rcdata),
+        _ => None,
+    }
+}
+
+// The tree builder's insertion modes, a subset of those in the HTML standard.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InsertionMode {
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    AfterHead,
+    InBody,
+    AfterBody,
+}
+
+// Elements that belong in <head> when they appear before the body starts.
+const HEAD_ELEMENTS: &[&str] = &[
+    "base", "link", "meta", "noscript", "script", "style", "template", "title",
+];
+
+// Block-level elements whose start tag implicitly closes an open <p>.
+const CLOSES_P: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "center",
+    "dd",
+    "details",
+    "dialog",
+    "dir",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hgroup",
+    "hr",
+    "li",
+    "listing",
+    "main",
+    "menu",
+    "nav",
+    "ol",
+    "p",
+    "plaintext",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "ul",
+    "xmp",
+];
+
+// Elements whose end tags may be omitted without it being an error.
+const IMPLIED_END_TAGS: &[&str] = &[
+    "body", "dd", "dt", "head", "html", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+    "tbody", "td", "tfoot", "th", "thead", "tr",
+];
+
+/// Elements that never have contents or an end tag.
+pub const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+// Elements that make up the structure of a table.
+const TABLE_PARTS: &[&str] = &["table", "tbody", "td", "tfoot", "th", "thead", "tr"];
+
+// Elements that bound the "has an element in scope" search.
+const SCOPE_BOUNDARIES: &[&str] = &[
+    "applet", "caption", "html", "marquee", "object", "table", "td", "template", "th",
+];
+
+/// Splits HTML source into tokens. The source can be supplied in chunks as it arrives, and each
+/// token is produced as soon as all of it has been received.
+///
+/// After the start tag of an element like `<script>` or `<textarea>`, the tokenizer reads the
+/// element's contents as a single text token rather than as markup.
+pub struct Tokenizer {
     pos: usize,
+    // The input that hasn't been read yet, and the partial token before it, if any. Earlier
+    // input is dropped once it has been read.
     input: String,
+    // The byte offset in the whole source of the start of `input`.
+    base: usize,
+    lines: LineIndex,
+    // Set once the last chunk of input has been supplied.
+    input_ended: bool,
+    // Set when reading a token looks beyond the input received so far, meaning that the token
+    // may continue in the next chunk.
+    reached_end: Cell<bool>,
+    // When a token was cut off by the end of the input, where it starts and how far it was
+    // scanned, so that reading it again can carry on from there.
+    resume: Option<(usize, usize)>,
+
+    // Set after the start tag of an element whose contents aren't parsed as markup.
+    raw_text: Option<(String, RawTextKind)>,
+
+    /// Recoverable errors, such as unterminated comments, found so far.
+    pub errors: Vec<ParseError>,
 }
 
-impl Parser {
+impl Default for Tokenizer {
+    fn default() -> Tokenizer {
+        Tokenizer::new()
+    }
+}
+
+impl Tokenizer {
+    pub fn new() -> Tokenizer {
+        Tokenizer {
+            pos: 0,
+            input: String::new(),
+            base: 0,
+            lines: LineIndex::new(""),
+            input_ended: false,
+            reached_end: Cell::new(false),
+            resume: None,
+            raw_text: None,
+            errors: vec![],
+        }
+    }
+
+    /// Append the next chunk of source text.
+    pub fn feed(&mut self, chunk: &str) {
+        assert!(!self.input_ended, "input has already ended");
+
+        // Everything before the current position has been read.
+        self.input.drain(..self.pos);
+        self.base += self.pos;
+        if let Some((start, scanned)) = &mut self.resume {
+            *start -= self.pos;
+            *scanned -= self.pos;
+        }
+        self.pos = 0;
+
+        self.lines.extend(self.base + self.input.len(), chunk);
+        self.input.push_str(chunk);
+    }
+
+    /// Mark the end of the input, so that tokens cut short by it are completed.
+    pub fn end(&mut self) {
+        self.input_ended = true;
+    }
+
+    /// Read the next token and the span of source it came from. Returns `None` when no complete
+    /// token is left: either more input is needed, or the input has ended.
+    pub fn next_token(&mut self) -> Option<(Token, Span)> {
+        let saved = (self.pos, self.raw_text.clone(), self.errors.len());
+        self.reached_end.set(false);
+        let result = self.read_token();
+
+        // A token that runs into the end of the input received so far may not be complete yet,
+        // so it is read again once there is more.
+        if self.reached_end.get() && !self.input_ended {
+            self.resume = Some((saved.0, self.pos));
+            (self.pos, self.raw_text) = (saved.0, saved.1);
+            self.errors.truncate(saved.2);
+            return None;
+        }
+        self.resume = None;
+        result
+    }
+
+    /// The location the tokenizer has read up to.
+    pub fn location(&self) -> Location {
+        self.lines.location(self.base + self.pos)
+    }
+
     // Read the current character without consuming it.
     fn next_char(&self) -> char {
         self.input[self.pos..].chars().next().unwrap()
     }
 
+    // Read the current character without consuming it, or `None` at the end of input.
+    fn next_char_opt(&self) -> Option<char> {
+        let c = self.input[self.pos..].chars().next();
+        if c.is_none() {
+            self.reached_end.set(true);
+        }
+        c
+    }
+
     // Do the next characters start with the given string?
     fn starts_with(&self, s: &str) -> bool {
-        self.input[self.pos..].starts_with(s)
+        let rest = &self.input[self.pos..];
+        if rest.len() < s.len() && s.starts_with(rest) {
+            self.reached_end.set(true);
+        }
+        rest.starts_with(s)
     }
 
     // Return true if all input is consumed.
     fn eof(&self) -> bool {
-        self.pos >= self.input.len()
+        let eof = self.pos >= self.input.len();
+        if eof {
+            self.reached_end.set(true);
+        }
+        eof
     }
 
     // Return the current character, and advance self.pos to the next character.
     fn consume_char(&mut self) -> char {
         let mut iter = self.input[self.pos..].char_indices();
         let (_, cur_char) = iter.next().unwrap();
-        let (next_pos, _) = iter.next().unwrap_or((1, ' '));
+        let (next_pos, _) = iter.next().unwrap_or((cur_char.len_utf8(), ' '));
         self.pos += next_pos;
         cur_char
     }
@@ -48,105 +328,1209 @@ impl Parser {
         self.consume_while(char::is_whitespace);
     }
 
-    // Parse a tag or attribute name.
+    // Build an error at the current position.
+    fn error(&self, expected: &str, found: String) -> ParseError {
+        ParseError::new(self.location(), expected, found)
+    }
+
+    // Record a recoverable error at an earlier position, e.g. the start of the current token.
+    fn report(&mut self, offset: usize, expected: &str, found: String) {
+        let location = self.lines.location(self.base + offset);
+        self.errors.push(ParseError::new(location, expected, found));
+    }
+
+    // Describe the current character for use in an error message.
+    fn found(&self) -> String {
+        if self.eof() {
+            String::from("end of input")
+        } else {
+            format!("{:?}", self.next_char())
+        }
+    }
+
+    // Consume the given character, or fail without consuming anything.
+    fn expect_char(&mut self, expected: char) -> ParseResult<()> {
+        if self.next_char_opt() == Some(expected) {
+            self.consume_char();
+            Ok(())
+        } else {
+            Err(self.error(&format!("{:?}", expected), self.found()))
+        }
+    }
+
+    // Parse a tag name. HTML tag names are case-insensitive, so they are ASCII-lowercased.
     fn parse_tag_name(&mut self) -> String {
-        self.consume_while(|c| match c {
-            'a'..='z' | 'A'..='Z' | '0'..='9' => true,
-            _ => false,
-        })
+        self.consume_while(|c| !c.is_whitespace() && c != '/' && c != '>')
+            .to_ascii_lowercase()
     }
 
-    // Parse a single node.
-    fn parse_node(&mut self) -> dom::Node {
-        match self.next_char() {
-            '<' => self.parse_element(),
-            _ => self.parse_text(),
+    // Parse an attribute name such as `href`, `data-role` or `xml:lang`, ASCII-lowercased.
+    // Quotes and `<` in the name, or an `=` at its start, are errors but kept in the name.
+    fn parse_attr_name(&mut self) -> String {
+        let mut name = String::new();
+        if self.next_char_opt() == Some('=') {
+            self.report(self.pos, "an attribute name", self.found());
+            name.push(self.consume_char());
+        }
+        loop {
+            name.push_str(&self.consume_while(|c| {
+                !c.is_whitespace() && !matches!(c, '/' | '>' | '=' | '"' | '\'' | '<')
+            }));
+            match self.next_char_opt() {
+                Some('"' | '\'' | '<') => {
+                    self.report(self.pos, "an attribute name", self.found());
+                    name.push(self.consume_char());
+                }
+                _ => break,
+            }
         }
+        name.to_ascii_lowercase()
     }
 
-    // Parse a text node.
-    fn parse_text(&mut self) -> dom::Node {
-        dom::text(self.consume_while(|c| c != '<'))
+    // Parse a name with `parse`, failing if it is empty.
+    fn expect_name<F>(&mut self, expected: &str, parse: F) -> ParseResult<String>
+    where
+        F: Fn(&mut Tokenizer) -> String,
+    {
+        let name = parse(self);
+        if name.is_empty() {
+            Err(self.error(expected, self.found()))
+        } else {
+            Ok(name)
+        }
     }
 
-    // Parse a single element, including its open tag, contents, and closing tag.
-    fn parse_element(&mut self) -> dom::Node {
-        // Opening tag
-        assert!(self.consume_char() == '<');
-        let tag_name = self.parse_tag_name();
-        let attrs = self.parse_attributes();
-        assert!(self.consume_char() == '>');
+    // Methods for tokenizing:
 
-        // Contents.
-        let children = self.parse_nodes();
+    // Does the input at the current position start a tag, comment, or other markup?
+    fn at_markup(&self) -> bool {
+        let rest = &self.input[self.pos..];
+        let mut chars = rest.chars();
+        if chars.next() != Some('<') {
+            return false;
+        }
+        let next = chars.next();
+        let after_next = chars.next();
+        if next.is_none() || (next == Some('/') && after_next.is_none()) {
+            self.reached_end.set(true);
+        }
+        match next {
+            Some('/') => after_next.is_some_and(|c| c.is_ascii_alphabetic()),
+            Some('!') | Some('?') => true,
+            Some(c) => c.is_ascii_alphabetic(),
+            None => false,
+        }
+    }
+
+    // Do the next characters start with the given string, ignoring ASCII case?
+    fn starts_with_ignore_case(&self, s: &str) -> bool {
+        let rest = &self.input.as_bytes()[self.pos..];
+        if rest.len() < s.len() {
+            if rest.eq_ignore_ascii_case(&s.as_bytes()[..rest.len()]) {
+                self.reached_end.set(true);
+            }
+            return false;
+        }
+        rest[..s.len()].eq_ignore_ascii_case(s.as_bytes())
+    }
 
-        // Closing tag.
-        assert!(self.consume_char() == '<');
-        assert!(self.consume_char() == '/');
-        assert!(self.parse_tag_name() == tag_name);
-        assert!(self.consume_char() == '>');
+    // Read the next token from the input received so far, or `None` at the end of it.
+    fn read_token(&mut self) -> Option<(Token, Span)> {
+        // The contents of an element like <script> are read verbatim, whitespace included.
+        if let Some((end_tag, kind)) = self.raw_text.take() {
+            let start = self.pos;
+            if let Some(text) = self.parse_raw_text(&end_tag, kind) {
+                return Some((text, self.span_from(start)));
+            }
+        }
 
-        dom::elem(tag_name, attrs, children)
+        let start = self.pos;
+        let token = if self.eof() {
+            return None;
+        } else if !self.at_markup() {
+            self.parse_text()
+        } else if self.starts_with("<!--") {
+            self.parse_comment()
+        } else if self.starts_with_ignore_case("<!DOCTYPE") {
+            self.parse_doctype()
+        } else if self.starts_with("<!") || self.starts_with("<?") {
+            self.parse_bogus_comment()
+        } else {
+            let tag = if self.starts_with("</") {
+                self.parse_end_tag()
+            } else {
+                self.parse_start_tag()
+            };
+            match tag {
+                Ok(tag) => tag,
+                // A tag can only fail to parse by running into the end of the input, in which
+                // case it is dropped.
+                Err(err) => {
+                    self.errors.push(err);
+                    self.pos = self.input.len();
+                    return None;
+                }
+            }
+        };
+        Some((token, self.span_from(start)))
     }
 
-    // Parse a single name="value" pair.
-    fn parse_attr(&mut self) -> (String, String) {
-        let name = self.parse_tag_name();
-        assert!(self.consume_char() == '=');
-        let value = self.parse_attr_value();
-        (name, value)
+    // The span of source from `start` to the current position.
+    fn span_from(&self, start: usize) -> Span {
+        self.lines.span(self.base + start, self.base + self.pos)
     }
 
-    // Parse a quoted value.
-    fn parse_attr_value(&mut self) -> String {
-        let open_quote = self.consume_char();
-        assert!(open_quote == '"' || open_quote == '\'');
-        let value = self.consume_while(|c| c != open_quote);
-        assert!(self.consume_char() == open_quote);
-        value
+    // Where to carry on scanning a text-like token that starts at `start`. If an earlier attempt
+    // to read it was cut off, the input it scanned is skipped, except for the last `overlap`
+    // bytes, which may hold the start of the delimiter that ends the token.
+    fn resume_from(&self, start: usize, overlap: usize) -> usize {
+        match self.resume {
+            Some((token_start, scanned)) if token_start == start => {
+                let mut pos = scanned.saturating_sub(overlap).max(start);
+                while !self.input.is_char_boundary(pos) {
+                    pos -= 1;
+                }
+                pos
+            }
+            _ => start,
+        }
     }
 
-    // Parse a list of name="value" pairs, separated by whitespace.
-    fn parse_attributes(&mut self) -> dom::AttrMap {
-        let mut attributes = HashMap::new();
+    // Parse a text token. A `<` that doesn't start a tag is kept as text.
+    fn parse_text(&mut self) -> Token {
+        let start = self.pos;
+        // A `<` cut off before the characters that show whether it starts a tag is checked again.
+        self.pos = self.resume_from(start, "</".len());
         loop {
-            self.consume_whitespace();
-            if self.next_char() == '>' {
+            self.skip_to('<');
+            if self.eof() || self.at_markup() {
                 break;
             }
-            let (name, value) = self.parse_attr();
-            attributes.insert(name, value);
+            self.consume_char();
         }
-        attributes
+        Token::Text(decode_character_references(
+            &self.input[start..self.pos],
+            false,
+        ))
+    }
+
+    // Move to the next occurrence of `c`, or to the end of the input.
+    fn skip_to(&mut self, c: char) {
+        self.pos += self.input[self.pos..]
+            .find(c)
+            .unwrap_or(self.input.len() - self.pos);
     }
 
-    // Parse a sequence of sibling nodes.
-    fn parse_nodes(&mut self) -> Vec<dom::Node> {
-        let mut nodes = Vec::new();
+    // Parse the contents of a raw text or RCDATA element, up to its end tag. Returns `None` if
+    // the element is empty.
+    fn parse_raw_text(&mut self, tag_name: &str, kind: RawTextKind) -> Option<Token> {
+        let start = self.pos;
+        self.pos = self.resume_from(start, "</".len() + tag_name.len());
         loop {
+            self.skip_to('<');
+            if self.eof() || self.at_end_tag(tag_name) {
+                break;
+            }
+            self.consume_char();
+        }
+        let text = &self.input[start..self.pos];
+        match kind {
+            _ if text.is_empty() => None,
+            RawTextKind::RawText => Some(Token::Text(text.to_string())),
+            RawTextKind::Rcdata => Some(Token::Text(decode_character_references(text, false))),
+        }
+    }
+
+    // Does the input at the current position start an end tag for `tag_name`?
+    fn at_end_tag(&self, tag_name: &str) -> bool {
+        let end_tag = format!("</{}", tag_name);
+        if !self.starts_with_ignore_case(&end_tag) {
+            return false;
+        }
+        match self.input[self.pos + end_tag.len()..].chars().next() {
+            Some(c) => c.is_whitespace() || c == '/' || c == '>',
+            None => {
+                self.reached_end.set(true);
+                true
+            }
+        }
+    }
+
+    // Parse a `<!-- comment -->`. An unterminated comment runs to the end of input.
+    fn parse_comment(&mut self) -> Token {
+        let start = self.pos;
+        self.pos += "<!--".len();
+        let search_start = self.resume_from(start, "--".len()).max(self.pos);
+        let data = match self.input[search_start..].find("-->") {
+            Some(len) => {
+                let data = self.input[self.pos..search_start + len].to_string();
+                self.pos = search_start + len + "-->".len();
+                data
+            }
+            None => {
+                self.reached_end.set(true);
+                let data = self.input[self.pos..].to_string();
+                self.pos = self.input.len();
+                self.report(start, "\"-->\"", String::from("end of input"));
+                data
+            }
+        };
+        Token::Comment(data)
+    }
+
+    // Parse markup such as `<?xml ...?>` or `<!ELEMENT ...>`, which HTML treats as a comment.
+    fn parse_bogus_comment(&mut self) -> Token {
+        let start = self.pos;
+        self.consume_char();
+        if self.next_char() == '!' {
+            self.consume_char();
+        }
+        let data = self.consume_while(|c| c != '>');
+        if self.eof() {
+            self.report(start, "'>'", String::from("end of input"));
+        } else {
+            self.consume_char();
+        }
+        Token::Comment(data)
+    }
+
+    // Parse a `<!DOCTYPE name PUBLIC "public id" "system id">` declaration.
+    fn parse_doctype(&mut self) -> Token {
+        let start = self.pos;
+        self.pos += "<!DOCTYPE".len();
+        self.consume_whitespace();
+        let name = self
+            .consume_while(|c| !c.is_whitespace() && c != '>')
+            .to_ascii_lowercase();
+        let mut force_quirks = name.is_empty();
+        let mut public_id = None;
+        let mut system_id = None;
+
+        self.consume_whitespace();
+        if self.starts_with_ignore_case("PUBLIC") {
+            self.pos += "PUBLIC".len();
+            self.consume_whitespace();
+            public_id = self.parse_doctype_id();
+            force_quirks |= public_id.is_none();
+            self.consume_whitespace();
+            system_id = self.parse_doctype_id();
+        } else if self.starts_with_ignore_case("SYSTEM") {
+            self.pos += "SYSTEM".len();
             self.consume_whitespace();
-            if self.eof() || self.starts_with("</") {
+            system_id = self.parse_doctype_id();
+            force_quirks |= system_id.is_none();
+        }
+
+        // Skip anything else up to the end of the declaration.
+        self.consume_while(|c| c != '>');
+        if self.eof() {
+            self.report(start, "'>'", String::from("end of input"));
+            force_quirks = true;
+        } else {
+            self.consume_char();
+        }
+
+        Token::Doctype {
+            doctype: dom::Doctype {
+                name,
+                public_id,
+                system_id,
+            },
+            force_quirks,
+        }
+    }
+
+    // Parse a quoted public or system identifier in a doctype, if there is one.
+    fn parse_doctype_id(&mut self) -> Option<String> {
+        let quote = match self.next_char_opt() {
+            Some(c @ '"') | Some(c @ '\'') => c,
+            _ => return None,
+        };
+        self.consume_char();
+        let id = self.consume_while(|c| c != quote && c != '>');
+        if self.next_char_opt() == Some(quote) {
+            self.consume_char();
+            Some(id)
+        } else {
+            // An identifier cut short by `>` is malformed.
+            None
+        }
+    }
+
+    // Parse an opening tag, including its attributes.
+    fn parse_start_tag(&mut self) -> ParseResult<Token> {
+        self.expect_char('<')?;
+        let name = self.expect_name("tag name", Tokenizer::parse_tag_name)?;
+        let attrs = self.parse_attributes()?;
+        let self_closing = self.starts_with("/>");
+        if self_closing {
+            self.consume_char();
+        }
+        self.expect_char('>')?;
+        if !self_closing {
+            self.raw_text = raw_text_kind(&name).map(|kind| (name.clone(), kind));
+        }
+        Ok(Token::StartTag {
+            name,
+            attrs,
+            self_closing,
+        })
+    }
+
+    // Parse a closing tag.
+    fn parse_end_tag(&mut self) -> ParseResult<Token> {
+        self.expect_char('<')?;
+        self.expect_char('/')?;
+        let name = self.expect_name("tag name", Tokenizer::parse_tag_name)?;
+        // Attributes on an end tag are meaningless, but allowed by the tokenizer.
+        self.parse_attributes()?;
+        self.expect_char('>')?;
+        Ok(Token::EndTag { name })
+    }
+
+    // Parse a single attribute: `name="value"`, `name='value'`, `name=value`, or just `name`.
+    fn parse_attr(&mut self) -> ParseResult<(String, String)> {
+        let name = self.parse_attr_name();
+        self.consume_whitespace();
+        if self.next_char_opt() != Some('=') {
+            // A boolean attribute, whose value is the empty string.
+            return Ok((name, String::new()));
+        }
+        self.consume_char();
+        self.consume_whitespace();
+        let value = self.parse_attr_value()?;
+        Ok((name, value))
+    }
+
+    // Parse a quoted or unquoted value.
+    fn parse_attr_value(&mut self) -> ParseResult<String> {
+        let value = match self.next_char_opt() {
+            Some(open_quote @ '"') | Some(open_quote @ '\'') => {
+                self.consume_char();
+                let value = self.consume_while(|c| c != open_quote);
+                self.expect_char(open_quote)?;
+                value
+            }
+            _ => self.consume_while(|c| !c.is_whitespace() && c != '>'),
+        };
+        Ok(decode_character_references(&value, true))
+    }
+
+    // Parse a list of attributes, separated by whitespace.
+    fn parse_attributes(&mut self) -> ParseResult<dom::AttrMap> {
+        let mut attributes = HashMap::new();
+        loop {
+            self.consume_whitespace();
+            if self.eof() || self.next_char() == '>' || self.starts_with("/>") {
                 break;
             }
-            nodes.push(self.parse_node());
+            if self.next_char() == '/' {
+                // A stray slash between attributes is ignored.
+                self.consume_char();
+                continue;
+            }
+            let (name, value) = self.parse_attr()?;
+            // If an attribute is repeated, the first occurrence wins.
+            attributes.entry(name).or_insert(value);
         }
-        nodes
+        Ok(attributes)
     }
 }
 
-// Parse an HTML document and return the root element.
-pub fn parse(source: String) -> dom::Node {
-    let mut nodes = Parser {
-        pos: 0,
-        input: source,
+/// Builds a document tree from tokens.
+struct TreeBuilder {
+    mode: InsertionMode,
+    // The tree built so far, created when the root element is inserted.
+    document: Option<dom::Document>,
+    open_elements: Vec<dom::NodeId>,
+    errors: Vec<ParseError>,
+    // Where the token being processed came from.
+    token_span: Span,
+    // Set after a start tag whose element ignores a newline at the start of its contents.
+    skip_newline: bool,
+
+    // Document-level state:
+    doctype: Option<dom::Doctype>,
+    quirks_mode: dom::QuirksMode,
+    // Comments that appear before the root element starts, with their spans.
+    leading_comments: Vec<(String, Span)>,
+}
+
+impl TreeBuilder {
+    fn new() -> TreeBuilder {
+        TreeBuilder {
+            mode: InsertionMode::BeforeHtml,
+            document: None,
+            open_elements: vec![],
+            errors: vec![],
+            token_span: Span::default(),
+            skip_newline: false,
+            doctype: None,
+            quirks_mode: dom::QuirksMode::Quirks,
+            leading_comments: vec![],
+        }
+    }
+
+    // Complete the tree once all tokens have been processed.
+    fn into_document(mut self, end: Location) -> (dom::Document, Vec<ParseError>) {
+        self.finish(end);
+        let mut document = self.document.take().unwrap();
+        document.doctype = self.doctype.take();
+        document.mode = self.quirks_mode;
+        // Building the tree isn't a change that anyone needs to be told about.
+        document.take_records();
+        (document, self.errors)
+    }
+
+    // Record a recoverable error at `location`.
+    fn report(&mut self, location: Location, expected: &str, found: String) {
+        self.errors.push(ParseError::new(location, expected, found));
+    }
+
+    // Process a token that was read from `span`.
+    fn process_spanned_token(&mut self, token: Token, span: Span) {
+        self.token_span = span;
+
+        // A newline straight after `<pre>` only makes the markup easier to read.
+        let token = match token {
+            Token::Text(text) if mem::take(&mut self.skip_newline) => {
+                match text.strip_prefix('\n') {
+                    Some("") => return,
+                    Some(rest) => Token::Text(rest.to_string()),
+                    None => Token::Text(text),
+                }
+            }
+            token => {
+                self.skip_newline = false;
+                token
+            }
+        };
+        self.process_token(token, span.start);
     }
-    .parse_nodes();
 
-    // If the document contains a root element, just return it. Otherwise, create one.
-    if nodes.len() == 1 {
-        nodes.swap_remove(0)
+    // Dispatch a token according to the current insertion mode.
+    fn process_token(&mut self, token: Token, location: Location) {
+        // Comments and doctypes are handled the same way in every mode.
+        let token = match token {
+            Token::Comment(data) => {
+                if self.open_elements.is_empty() {
+                    self.leading_comments.push((data, self.token_span));
+                } else {
+                    let span = self.token_span;
+                    self.append(dom::NodeType::Comment(data), Some(span));
+                }
+                return;
+            }
+            Token::Doctype {
+                doctype,
+                force_quirks,
+            } => {
+                if self.mode == InsertionMode::BeforeHtml && self.doctype.is_none() {
+                    self.quirks_mode = quirks_mode(&doctype, force_quirks);
+                    self.doctype = Some(doctype);
+                } else {
+                    self.report(location, "content", String::from("<!DOCTYPE>"));
+                }
+                return;
+            }
+            token => token,
+        };
+
+        // Whitespace around the elements that make up the outline of the document is kept
+        // where it can be, but doesn't start the body the way other text does.
+        let token = match token {
+            Token::Text(text) if self.in_outline() => {
+                let rest = text.trim_start_matches(is_html_whitespace);
+                let whitespace = &text[..text.len() - rest.len()];
+                let dropped = matches!(
+                    self.mode,
+                    InsertionMode::BeforeHtml | InsertionMode::BeforeHead
+                );
+                if !whitespace.is_empty() && !dropped {
+                    self.insert_text(whitespace.to_string());
+                }
+                if rest.is_empty() {
+                    return;
+                }
+                Token::Text(rest.to_string())
+            }
+            token => token,
+        };
+
+        match self.mode {
+            InsertionMode::BeforeHtml => self.before_html(token, location),
+            InsertionMode::BeforeHead => self.before_head(token, location),
+            InsertionMode::InHead => self.in_head(token, location),
+            InsertionMode::AfterHead => self.after_head(token, location),
+            InsertionMode::InBody => self.in_body(token, location),
+            InsertionMode::AfterBody => self.after_body(token, location),
+        }
+    }
+
+    // Is the builder between the structural elements, rather than inside the body or an element
+    // in the head?
+    fn in_outline(&self) -> bool {
+        match self.mode {
+            InsertionMode::InBody => false,
+            InsertionMode::InHead => self.current_name() == "head",
+            _ => true,
+        }
+    }
+
+    fn before_html(&mut self, token: Token, location: Location) {
+        match token {
+            Token::StartTag { ref name, .. } if name == "html" => {
+                self.insert_element(token);
+                self.mode = InsertionMode::BeforeHead;
+            }
+            Token::EndTag { ref name } if !is_one_of(name, &["head", "body", "html", "br"]) => {
+                self.report(location, "<html>", format!("</{}>", name));
+            }
+            _ => {
+                self.insert_synthesized("html");
+                self.mode = InsertionMode::BeforeHead;
+                self.process_token(token, location);
+            }
+        }
+    }
+
+    fn before_head(&mut self, token: Token, location: Location) {
+        match token {
+            Token::StartTag { ref name, .. } if name == "head" => {
+                self.insert_element(token);
+                self.mode = InsertionMode::InHead;
+            }
+            Token::StartTag { ref name, .. } if name == "html" => {
+                self.report(location, "<head>", String::from("<html>"));
+            }
+            Token::EndTag { ref name } if !is_one_of(name, &["head", "body", "html", "br"]) => {
+                self.report(location, "<head>", format!("</{}>", name));
+            }
+            _ => {
+                self.insert_synthesized("head");
+                self.mode = InsertionMode::InHead;
+                self.process_token(token, location);
+            }
+        }
+    }
+
+    fn in_head(&mut self, token: Token, location: Location) {
+        let in_head_child = self.current_name() != "head";
+        match token {
+            Token::StartTag { ref name, .. } if is_one_of(name, HEAD_ELEMENTS) => {
+                self.insert_element(token);
+            }
+            Token::StartTag { ref name, .. } if name == "head" => {
+                self.report(location, "content", String::from("<head>"));
+            }
+            Token::Text(text) if in_head_child => self.insert_text(text),
+            Token::EndTag { ref name } if in_head_child && name == self.current_name() => {
+                self.pop();
+            }
+            Token::EndTag { ref name } if name == "head" => {
+                self.close_head(location);
+            }
+            Token::EndTag { ref name } if !is_one_of(name, &["body", "html", "br"]) => {
+                self.report(location, "</head>", format!("</{}>", name));
+            }
+            _ => {
+                self.close_head(location);
+                self.process_token(token, location);
+            }
+        }
+    }
+
+    // Pop elements up to and including <head>, and move on to the body.
+    fn close_head(&mut self, location: Location) {
+        while self.current_name() != "head" {
+            let name = self.current_name().to_string();
+            self.report(location, &format!("</{}>", name), String::from("</head>"));
+            self.pop();
+        }
+        self.pop();
+        self.mode = InsertionMode::AfterHead;
+    }
+
+    fn after_head(&mut self, token: Token, location: Location) {
+        match token {
+            Token::StartTag { ref name, .. } if name == "body" => {
+                self.insert_element(token);
+                self.mode = InsertionMode::InBody;
+            }
+            Token::StartTag { ref name, .. } if name == "head" => {
+                self.report(location, "<body>", String::from("<head>"));
+            }
+            Token::EndTag { ref name } if !is_one_of(name, &["body", "html", "br"]) => {
+                self.report(location, "<body>", format!("</{}>", name));
+            }
+            _ => {
+                self.insert_synthesized("body");
+                self.mode = InsertionMode::InBody;
+                self.process_token(token, location);
+            }
+        }
+    }
+
+    fn in_body(&mut self, token: Token, location: Location) {
+        match token {
+            Token::Text(text) => self.insert_text(text),
+            Token::StartTag { ref name, .. } if is_one_of(name, &["html", "body", "head"]) => {
+                self.report(location, "content", format!("<{}>", name));
+            }
+            Token::StartTag { .. } => {
+                self.close_implied_by_start_tag(&token, location);
+                self.insert_element(token);
+            }
+            Token::EndTag { ref name } if name == "body" || name == "html" => {
+                if self.in_scope("body", &[]) {
+                    self.mode = InsertionMode::AfterBody;
+                } else {
+                    self.report(location, "content", format!("</{}>", name));
+                }
+            }
+            Token::EndTag { ref name } if name == "p" && !self.in_scope("p", &["button"]) => {
+                // A stray </p> creates an empty paragraph.
+                self.report(location, "<p>", String::from("</p>"));
+                self.insert_synthesized("p");
+                self.pop();
+            }
+            Token::EndTag { ref name } if name == "br" => {
+                // A stray </br> is treated as <br>.
+                self.report(location, "<br>", String::from("</br>"));
+                self.process_token(
+                    Token::StartTag {
+                        name: String::from("br"),
+                        attrs: dom::AttrMap::new(),
+                        self_closing: false,
+                    },
+                    location,
+                );
+            }
+            Token::EndTag { ref name } if is_one_of(name, TABLE_PARTS) => {
+                // Cells and table elements are scope boundaries for other end tags, so they are
+                // searched for in table scope, closing any cells or rows left open inside.
+                if self.find_in_table_scope(&[name]).is_some() {
+                    self.close_element(name, location);
+                } else {
+                    self.report(location, "matching start tag", format!("</{}>", name));
+                }
+            }
+            Token::EndTag { ref name } => {
+                let extra: &[&str] = match &name[..] {
+                    "p" => &["button"],
+                    "li" => &["ol", "ul"],
+                    _ => &[],
+                };
+                if self.in_scope(name, extra) {
+                    self.close_element(name, location);
+                } else {
+                    self.report(location, "matching start tag", format!("</{}>", name));
+                }
+            }
+            // Already handled by `process_token`.
+            Token::Comment(_) | Token::Doctype { .. } => {}
+        }
+    }
+
+    fn after_body(&mut self, token: Token, location: Location) {
+        match token {
+            Token::EndTag { ref name } if name == "html" || name == "body" => {}
+            _ => {
+                let found = match token {
+                    Token::StartTag { ref name, .. } => format!("<{}>", name),
+                    Token::EndTag { ref name } => format!("</{}>", name),
+                    _ => String::from("text"),
+                };
+                self.report(location, "end of input", found);
+                self.mode = InsertionMode::InBody;
+                self.process_token(token, location);
+            }
+        }
+    }
+
+    // Close any open elements that a start tag implicitly ends, e.g. a <p> before a <div>.
+    fn close_implied_by_start_tag(&mut self, token: &Token, location: Location) {
+        let name = match token {
+            Token::StartTag { name, .. } => &name[..],
+            _ => return,
+        };
+
+        if is_one_of(name, CLOSES_P) && self.in_scope("p", &["button"]) {
+            self.close_element("p", location);
+        }
+
+        match name {
+            "li" => self.close_list_item(&["li"], location),
+            "dd" | "dt" => self.close_list_item(&["dd", "dt"], location),
+            "td" | "th" => {
+                if let Some(cell) = self.find_in_table_scope(&["td", "th"]) {
+                    self.close_element(&cell, location);
+                }
+            }
+            "tr" if self.find_in_table_scope(&["tr"]).is_some() => {
+                self.close_element("tr", location);
+            }
+            "option" if self.current_name() == "option" => self.pop(),
+            "optgroup" => {
+                if self.current_name() == "option" {
+                    self.pop();
+                }
+                if self.current_name() == "optgroup" {
+                    self.pop();
+                }
+            }
+            _ if is_one_of(name, HEADINGS) && is_one_of(self.current_name(), HEADINGS) => {
+                let current = self.current_name().to_string();
+                self.report(location, &format!("</{}>", current), format!("<{}>", name));
+                self.pop();
+            }
+            _ => {}
+        }
+    }
+
+    // Close an open list item with one of the given names, unless a block element is in the way.
+    fn close_list_item(&mut self, names: &[&str], location: Location) {
+        for &id in self.open_elements.iter().rev() {
+            let node_name = self.tag_name(id);
+            if is_one_of(node_name, names) {
+                let node_name = node_name.to_string();
+                self.close_element(&node_name, location);
+                return;
+            }
+            if !is_one_of(node_name, &["address", "div", "p"]) && is_block_boundary(node_name) {
+                return;
+            }
+        }
+    }
+
+    // Find an open element with one of the given names, searching no further than a table.
+    fn find_in_table_scope(&self, names: &[&str]) -> Option<String> {
+        for &id in self.open_elements.iter().rev() {
+            let node_name = self.tag_name(id);
+            if is_one_of(node_name, names) {
+                return Some(node_name.to_string());
+            }
+            if is_one_of(node_name, &["html", "table", "template"]) {
+                return None;
+            }
+        }
+        None
+    }
+
+    // Is there an open element named `name` that isn't hidden behind a scope boundary?
+    fn in_scope(&self, name: &str, extra_boundaries: &[&str]) -> bool {
+        for &id in self.open_elements.iter().rev() {
+            let node_name = self.tag_name(id);
+            if node_name == name {
+                return true;
+            }
+            if is_one_of(node_name, SCOPE_BOUNDARIES) || is_one_of(node_name, extra_boundaries) {
+                return false;
+            }
+        }
+        false
+    }
+
+    // Pop elements until one named `name` has been popped, reporting any that weren't closed.
+    fn close_element(&mut self, name: &str, location: Location) {
+        while self.current_name() != name {
+            let current = self.current_name().to_string();
+            if !is_one_of(&current, IMPLIED_END_TAGS) {
+                self.report(location, &format!("</{}>", current), format!("</{}>", name));
+            }
+            self.pop();
+        }
+        self.pop();
+    }
+
+    // Close all remaining elements at the end of input, leaving only the root on the stack.
+    fn finish(&mut self, end: Location) {
+        if self.open_elements.is_empty() {
+            self.insert_synthesized("html");
+        }
+        while self.open_elements.len() > 1 {
+            let current = self.current_name().to_string();
+            if !is_one_of(&current, IMPLIED_END_TAGS) {
+                self.report(
+                    end,
+                    &format!("</{}>", current),
+                    String::from("end of input"),
+                );
+            }
+            self.pop();
+        }
+    }
+
+    // The tag name of the current node, i.e. the innermost open element.
+    fn current_name(&self) -> &str {
+        self.tag_name(*self.open_elements.last().unwrap())
+    }
+
+    // The tag name of an element in the tree being built.
+    fn tag_name(&self, id: dom::NodeId) -> &str {
+        let document = self.document.as_ref().unwrap();
+        match document[id].node_type {
+            dom::NodeType::Element(ref elem) => &elem.tag_name,
+            dom::NodeType::Text(_) | dom::NodeType::Comment(_) => "",
+        }
+    }
+
+    // Push a new element for a start tag onto the stack of open elements. Void elements and
+    // self-closing tags have no contents, so they are closed again straight away.
+    fn insert_element(&mut self, token: Token) {
+        if let Token::StartTag {
+            name,
+            attrs,
+            self_closing,
+        } = token
+        {
+            let closed = self_closing || is_one_of(&name, VOID_ELEMENTS);
+            self.skip_newline = !closed && is_one_of(&name, &["listing", "pre", "textarea"]);
+            self.push_element(name, attrs, Some(self.token_span));
+            if closed {
+                self.pop();
+            }
+        }
+    }
+
+    // Push an element whose start tag was omitted from the source.
+    fn insert_synthesized(&mut self, name: &str) {
+        self.push_element(name.to_string(), HashMap::new(), None);
+    }
+
+    fn push_element(&mut self, name: String, attrs: dom::AttrMap, span: Option<Span>) {
+        let element = dom::NodeType::Element(dom::ElementData {
+            tag_name: name,
+            attributes: attrs,
+        });
+        let id = match self.document {
+            Some(_) => self.append(element, span),
+            None => {
+                // This is the root element, which adopts any comments seen so far.
+                let mut document = dom::Document::new(element);
+                let root = document.root().id();
+                document.node_mut(root).span = span;
+                for (data, span) in self.leading_comments.drain(..) {
+                    let comment = document.create_node(dom::NodeType::Comment(data));
+                    document.node_mut(comment).span = Some(span);
+                    document.append_child(root, comment);
+                }
+                self.document = Some(document);
+                root
+            }
+        };
+        self.open_elements.push(id);
+    }
+
+    // Append a text node to the current node.
+    // Text inserted right after a text node is added to it, as when more text follows the end
+    // tag of the body.
+    fn insert_text(&mut self, text: String) {
+        let span = self.token_span;
+        let parent = *self.open_elements.last().unwrap();
+        let document = self.document.as_mut().unwrap();
+        if let Some(last) = document.get(parent).last_child().map(|node| node.id()) {
+            if let dom::NodeType::Text(ref mut data) = document.node_mut(last).node_type {
+                data.push_str(&text);
+                if let Some(ref mut last_span) = document.node_mut(last).span {
+                    last_span.end = span.end;
+                }
+                return;
+            }
+        }
+        self.append(dom::NodeType::Text(text), Some(span));
+    }
+
+    // Append a new node to the current node.
+    fn append(&mut self, node_type: dom::NodeType, span: Option<Span>) -> dom::NodeId {
+        let parent = *self.open_elements.last().unwrap();
+        let document = self.document.as_mut().unwrap();
+        let id = document.create_node(node_type);
+        document.node_mut(id).span = span;
+        document.append_child(parent, id);
+        id
+    }
+
+    // Pop the current node. It was attached to its parent when it was inserted.
+    fn pop(&mut self) {
+        self.open_elements.pop();
+    }
+}
+
+// Replace character references such as `&amp;`, `&#169;` and `&#x1F600;` with the characters
+// they stand for. References that don't resolve are left as they are.
+// https://html.spec.whatwg.org/multipage/parsing.html#character-reference-state
+fn decode_character_references(input: &str, in_attribute: bool) -> String {
+    let mut result = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(i) = rest.find('&') {
+        result.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        let decoded = if let Some(numeric) = rest.strip_prefix('#') {
+            decode_numeric_reference(numeric).map(|(c, len)| (c.to_string(), len + 1))
+        } else {
+            decode_named_reference(rest, in_attribute).map(|(s, len)| (s.to_string(), len))
+        };
+        match decoded {
+            Some((replacement, len)) => {
+                result.push_str(&replacement);
+                rest = &rest[len..];
+            }
+            None => result.push('&'),
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// Decode the longest entity name at the start of `input`, which follows an `&`. Returns the
+// replacement text and the number of bytes used.
+fn decode_named_reference(input: &str, in_attribute: bool) -> Option<(&'static str, usize)> {
+    let name_len = input
+        .bytes()
+        .take(entities::MAX_NAME_LEN)
+        .take_while(u8::is_ascii_alphanumeric)
+        .count();
+    let max_len = if input[name_len..].starts_with(';') {
+        name_len + 1
     } else {
-        dom::elem(String::from("html"), HashMap::new(), nodes)
+        name_len
+    };
+
+    let (replacement, len) = (1..=max_len)
+        .rev()
+        .find_map(|len| entities::lookup(&input[..len]).map(|s| (s, len)))?;
+
+    // For historical reasons, a legacy reference without a `;` isn't decoded in an attribute
+    // value when it's followed by something that could continue the name, as in `?a=1&copy=2`.
+    if in_attribute && !input[..len].ends_with(';') {
+        if let Some(next) = input[len..].chars().next() {
+            if next == '=' || next.is_ascii_alphanumeric() {
+                return None;
+            }
+        }
+    }
+    Some((replacement, len))
+}
+
+// Decode a numeric reference such as `169;` or `x1F600;`, which follows an `&#`. Returns the
+// character and the number of bytes used.
+fn decode_numeric_reference(input: &str) -> Option<(char, usize)> {
+    let (radix, prefix_len) = match input.chars().next() {
+        Some('x') | Some('X') => (16, 1),
+        _ => (10, 0),
+    };
+    let digits_len = input[prefix_len..]
+        .chars()
+        .take_while(|c| c.is_digit(radix))
+        .count();
+    if digits_len == 0 {
+        return None;
+    }
+    let digits = &input[prefix_len..prefix_len + digits_len];
+    let mut len = prefix_len + digits_len;
+    if input[len..].starts_with(';') {
+        len += 1;
+    }
+
+    // Saturate huge values rather than overflowing; they are out of range either way.
+    let value = digits.chars().fold(0u32, |value, c| {
+        value
+            .saturating_mul(radix)
+            .saturating_add(c.to_digit(radix).unwrap())
+    });
+    Some((numeric_reference_char(value), len))
+}
+
+// The character a numeric reference stands for, replacing invalid code points.
+fn numeric_reference_char(value: u32) -> char {
+    match value {
+        // Documents commonly use C1 control codes by mistake for windows-1252 characters.
+        0x80..=0x9F => encoding::WINDOWS_1252_C1[(value - 0x80) as usize],
+        // NUL, surrogates and values beyond Unicode become U+FFFD REPLACEMENT CHARACTER.
+        _ => std::char::from_u32(value)
+            .filter(|&c| c != '\0')
+            .unwrap_or('\u{FFFD}'),
+    }
+}
+
+// The characters that HTML treats as whitespace.
+fn is_html_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | '\r' | ' ')
+}
+
+fn is_one_of(name: &str, names: &[&str]) -> bool {
+    names.contains(&name)
+}
+
+// Block-level containers that stop the search for an open list item.
+fn is_block_boundary(name: &str) -> bool {
+    is_one_of(name, CLOSES_P) || is_one_of(name, SCOPE_BOUNDARIES) || name == "body"
+}
+
+// Public identifier prefixes that put a document in quirks mode.
+const QUIRKS_PUBLIC_ID_PREFIXES: &[&str] = &[
+    "+//silmaril//dtd html pro v0r11 19970101//",
+    "-//as//dtd html 3.0 aswedit + extensions//",
+    "-//advasoft ltd//dtd html 3.0 aswedit + extensions//",
+    "-//ietf//dtd html 2.0 level 1//",
+    "-//ietf//dtd html 2.0 level 2//",
+    "-//ietf//dtd html 2.0 strict level 1//",
+    "-//ietf//dtd html 2.0 strict level 2//",
+    "-//ietf//dtd html 2.0 strict//",
+    "-//ietf//dtd html 2.0//",
+    "-//ietf//dtd html 2.1e//",
+    "-//ietf//dtd html 3.0//",
+    "-//ietf//dtd html 3.2 final//",
+    "-//ietf//dtd html 3.2//",
+    "-//ietf//dtd html 3//",
+    "-//ietf//dtd html level 0//",
+    "-//ietf//dtd html level 1//",
+    "-//ietf//dtd html level 2//",
+    "-//ietf//dtd html level 3//",
+    "-//ietf//dtd html strict level 0//",
+    "-//ietf//dtd html strict level 1//",
+    "-//ietf//dtd html strict level 2//",
+    "-//ietf//dtd html strict level 3//",
+    "-//ietf//dtd html strict//",
+    "-//ietf//dtd html//",
+    "-//metrius//dtd metrius presentational//",
+    "-//microsoft//dtd internet explorer 2.0 html strict//",
+    "-//microsoft//dtd internet explorer 2.0 html//",
+    "-//microsoft//dtd internet explorer 2.0 tables//",
+    "-//microsoft//dtd internet explorer 3.0 html strict//",
+    "-//microsoft//dtd internet explorer 3.0 html//",
+    "-//microsoft//dtd internet explorer 3.0 tables//",
+    "-//netscape comm. corp.//dtd html//",
+    "-//netscape comm. corp.//dtd strict html//",
+    "-//o'reilly and associates//dtd html 2.0//",
+    "-//o'reilly and associates//dtd html extended 1.0//",
+    "-//o'reilly and associates//dtd html extended relaxed 1.0//",
+    "-//sq//dtd html 2.0 hotmetal + extensions//",
+    "-//softquad software//dtd hotmetal pro 6.0::19990601::extensions to html 4.0//",
+    "-//softquad//dtd hotmetal pro 4.0::19971010::extensions to html 4.0//",
+    "-//spyglass//dtd html 2.0 extended//",
+    "-//sun microsystems corp.//dtd hotjava html//",
+    "-//sun microsystems corp.//dtd hotjava strict html//",
+    "-//w3c//dtd html 3 1995-03-24//",
+    "-//w3c//dtd html 3.2 draft//",
+    "-//w3c//dtd html 3.2 final//",
+    "-//w3c//dtd html 3.2//",
+    "-//w3c//dtd html 3.2s draft//",
+    "-//w3c//dtd html 4.0 frameset//",
+    "-//w3c//dtd html 4.0 transitional//",
+    "-//w3c//dtd html experimental 19960712//",
+    "-//w3c//dtd html experimental 970421//",
+    "-//w3c//dtd w3 html//",
+    "-//w3o//dtd w3 html 3.0//",
+    "-//webtechs//dtd mozilla html 2.0//",
+    "-//webtechs//dtd mozilla html//",
+];
+
+/// Determine the document mode selected by a doctype.
+/// https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode
+fn quirks_mode(doctype: &dom::Doctype, force_quirks: bool) -> dom::QuirksMode {
+    let public_id = doctype.public_id.as_deref().map(str::to_ascii_lowercase);
+    let system_id = doctype.system_id.as_deref().map(str::to_ascii_lowercase);
+    let public_id = public_id.as_deref().unwrap_or("");
+    let has_system_id = system_id.is_some();
+    let html4_frameset_or_transitional = public_id.starts_with("-//w3c//dtd html 4.01 frameset//")
+        || public_id.starts_with("-//w3c//dtd html 4.01 transitional//");
+
+    if force_quirks
+        || doctype.name != "html"
+        || public_id == "-//w3o//dtd w3 html strict 3.0//en//"
+        || public_id == "-/w3c/dtd html 4.0 transitional/en"
+        || public_id == "html"
+        || system_id.as_deref()
+            == Some("http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd")
+        || QUIRKS_PUBLIC_ID_PREFIXES
+            .iter()
+            .any(|prefix| public_id.starts_with(prefix))
+        || (!has_system_id && html4_frameset_or_transitional)
+    {
+        dom::QuirksMode::Quirks
+    } else if public_id.starts_with("-//w3c//dtd xhtml 1.0 frameset//")
+        || public_id.starts_with("-//w3c//dtd xhtml 1.0 transitional//")
+        || (has_system_id && html4_frameset_or_transitional)
+    {
+        dom::QuirksMode::LimitedQuirks
+    } else {
+        dom::QuirksMode::NoQuirks
+    }
+}
+
+/// Parse an HTML document. The root of the returned tree is the `<html>` element.
+///
+/// Malformed markup is repaired the way browsers do it: missing `<html>`, `<head>` and `<body>`
+/// elements are synthesized, unclosed or mismatched tags are closed, and a tag cut off by the
+/// end of the input is dropped. Every error is recovered from; use `parse_with_errors` to find
+/// out what was repaired.
+pub fn parse(source: String) -> dom::Document {
+    parse_with_errors(source).0
+}
+
+/// Parse an HTML document, also returning the errors that were recovered from.
+pub fn parse_with_errors(source: String) -> (dom::Document, Vec<ParseError>) {
+    let mut parser = Parser::new();
+    parser.feed(&source);
+    parser.finish()
+}
+
+/// Split a whole HTML document into tokens, along with the spans they came from.
+pub fn tokenize(source: &str) -> Vec<(Token, Span)> {
+    let mut tokenizer = Tokenizer::new();
+    tokenizer.feed(source);
+    tokenizer.end();
+    let mut tokens = vec![];
+    while let Some(token) = tokenizer.next_token() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Parses an HTML document that arrives in chunks, building the tree as tokens become complete.
+pub struct Parser {
+    tokenizer: Tokenizer,
+    builder: TreeBuilder,
+}
+
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser {
+            tokenizer: Tokenizer::new(),
+            builder: TreeBuilder::new(),
+        }
+    }
+
+    /// Parse the next chunk of the document. Markup cut off at the end of the chunk is kept
+    /// until the rest of it arrives.
+    pub fn feed(&mut self, chunk: &str) {
+        self.tokenizer.feed(chunk);
+        self.process_tokens();
+    }
+
+    /// Parse whatever input is left and return the document, along with the errors that were
+    /// recovered from.
+    pub fn finish(mut self) -> (dom::Document, Vec<ParseError>) {
+        self.tokenizer.end();
+        self.process_tokens();
+        self.builder.into_document(self.tokenizer.location())
+    }
+
+    fn process_tokens(&mut self) {
+        while let Some((token, span)) = self.tokenizer.next_token() {
+            // Errors are kept in the order they were found in the source.
+            self.builder.errors.append(&mut self.tokenizer.errors);
+            self.builder.process_spanned_token(token, span);
+        }
+        self.builder.errors.append(&mut self.tokenizer.errors);
     }
 }
 
@@ -154,9 +1538,9 @@ pub fn parse(source: String) -> dom::Node {
 mod tests {
     use std::collections::HashMap;
 
-    use crate::dom::{elem, text};
+    use crate::dom::{comment, elem, text, Doctype, NodeRef, NodeType, QuirksMode};
 
-    use super::parse;
+    use super::{parse, parse_with_errors, tokenize, Location, ParseError, Parser, Token};
 
     #[test]
     fn test_dom() {
@@ -176,39 +1560,609 @@ mod tests {
         let mut div_attrs = HashMap::new();
         div_attrs.insert(String::from("id"), String::from("main"));
         div_attrs.insert(String::from("class"), String::from("test"));
+        // Whitespace before <body> is dropped, since neither <head> nor <body> was written.
         let expected = elem(
             String::from("html"),
             HashMap::new(),
-            vec![elem(
-                String::from("body"),
-                HashMap::new(),
-                vec![
-                    elem(
-                        String::from("h1"),
-                        HashMap::new(),
-                        vec![text(String::from("Title"))],
-                    ),
-                    elem(
-                        String::from("div"),
-                        div_attrs,
-                        vec![elem(
-                            String::from("p"),
+            vec![
+                elem(String::from("head"), HashMap::new(), vec![]),
+                elem(
+                    String::from("body"),
+                    HashMap::new(),
+                    vec![
+                        text(String::from("\n        ")),
+                        elem(
+                            String::from("h1"),
+                            HashMap::new(),
+                            vec![text(String::from("Title"))],
+                        ),
+                        text(String::from("\n        ")),
+                        elem(
+                            String::from("div"),
+                            div_attrs,
+                            vec![
+                                text(String::from("\n            ")),
+                                elem(
+                                    String::from("p"),
+                                    HashMap::new(),
+                                    vec![
+                                        text(String::from("Hello ")),
+                                        elem(
+                                            String::from("em"),
+                                            HashMap::new(),
+                                            vec![text(String::from("world"))],
+                                        ),
+                                        text(String::from("!")),
+                                    ],
+                                ),
+                                text(String::from("\n        ")),
+                            ],
+                        ),
+                        // Whitespace after the end tags of the body and the document goes into
+                        // the body too.
+                        text(String::from("\n    \n\n")),
+                    ],
+                ),
+            ],
+        );
+
+        assert_eq!(expected.root(), parse(source).root());
+    }
+
+    #[test]
+    fn test_mismatched_closing_tag() {
+        let source = String::from("<div>\n  <p>Hello</div>\n</p>");
+        let (document, errors) = parse_with_errors(source);
+        let root = document.root();
+
+        // </div> closes the open <p>, and the stray </p> becomes an empty paragraph.
+        let expected = elem(
+            String::from("html"),
+            HashMap::new(),
+            vec![
+                elem(String::from("head"), HashMap::new(), vec![]),
+                elem(
+                    String::from("body"),
+                    HashMap::new(),
+                    vec![
+                        elem(
+                            String::from("div"),
                             HashMap::new(),
                             vec![
-                                text(String::from("Hello ")),
+                                text(String::from("\n  ")),
                                 elem(
-                                    String::from("em"),
+                                    String::from("p"),
                                     HashMap::new(),
-                                    vec![text(String::from("world"))],
+                                    vec![text(String::from("Hello"))],
                                 ),
-                                text(String::from("!")),
                             ],
-                        )],
-                    ),
-                ],
-            )],
+                        ),
+                        text(String::from("\n")),
+                        elem(String::from("p"), HashMap::new(), vec![]),
+                    ],
+                ),
+            ],
+        );
+        assert_eq!(expected.root(), root);
+        assert_eq!(
+            vec![ParseError {
+                offset: 23,
+                line: 3,
+                column: 1,
+                expected: String::from("<p>"),
+                found: String::from("</p>"),
+            }],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_implied_end_tags() {
+        let source = String::from("<ul><li>one<li>two</ul><p>a<div>b</div>");
+        let (document, errors) = parse_with_errors(source);
+        let root = document.root();
+
+        let li = |s: &str| {
+            elem(
+                String::from("li"),
+                HashMap::new(),
+                vec![text(s.to_string())],
+            )
+        };
+        let expected_body = elem(
+            String::from("body"),
+            HashMap::new(),
+            vec![
+                elem(
+                    String::from("ul"),
+                    HashMap::new(),
+                    vec![li("one"), li("two")],
+                ),
+                elem(
+                    String::from("p"),
+                    HashMap::new(),
+                    vec![text(String::from("a"))],
+                ),
+                elem(
+                    String::from("div"),
+                    HashMap::new(),
+                    vec![text(String::from("b"))],
+                ),
+            ],
+        );
+        assert_eq!(expected_body.root(), root.children().nth(1).unwrap());
+        assert_eq!(Vec::<ParseError>::new(), errors);
+    }
+
+    #[test]
+    fn test_head_and_body_synthesized() {
+        let source = String::from("<title>Hi</title><p>Text");
+        let document = parse(source);
+        let root = document.root();
+
+        let expected = elem(
+            String::from("html"),
+            HashMap::new(),
+            vec![
+                elem(
+                    String::from("head"),
+                    HashMap::new(),
+                    vec![elem(
+                        String::from("title"),
+                        HashMap::new(),
+                        vec![text(String::from("Hi"))],
+                    )],
+                ),
+                elem(
+                    String::from("body"),
+                    HashMap::new(),
+                    vec![elem(
+                        String::from("p"),
+                        HashMap::new(),
+                        vec![text(String::from("Text"))],
+                    )],
+                ),
+            ],
+        );
+        assert_eq!(expected.root(), root);
+    }
+
+    #[test]
+    fn test_table_end_tags() {
+        let source = String::from("<table><tr><td>a<td>b</tr><tr><td>c</table><p>d</br>e</td>");
+        let (document, errors) = parse_with_errors(source);
+        let root = document.root();
+
+        let td = |s: &str| {
+            elem(
+                String::from("td"),
+                HashMap::new(),
+                vec![text(s.to_string())],
+            )
+        };
+        let expected_body = elem(
+            String::from("body"),
+            HashMap::new(),
+            vec![
+                elem(
+                    String::from("table"),
+                    HashMap::new(),
+                    vec![
+                        elem(String::from("tr"), HashMap::new(), vec![td("a"), td("b")]),
+                        elem(String::from("tr"), HashMap::new(), vec![td("c")]),
+                    ],
+                ),
+                // </br> is read as <br>, and a </td> outside a table is ignored.
+                elem(
+                    String::from("p"),
+                    HashMap::new(),
+                    vec![
+                        text(String::from("d")),
+                        elem(String::from("br"), HashMap::new(), vec![]),
+                        text(String::from("e")),
+                    ],
+                ),
+            ],
+        );
+        assert_eq!(expected_body.root(), root.children().nth(1).unwrap());
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|err| (err.offset, &err.expected[..], &err.found[..]))
+            .collect();
+        assert_eq!(
+            vec![(47, "<br>", "</br>"), (53, "matching start tag", "</td>")],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_void_and_self_closing_elements() {
+        let source = String::from(
+            r#"<p>one<br>two<img src="a.png"/><foo/>three</p><hr><input type="text">"#,
+        );
+        let (document, errors) = parse_with_errors(source);
+        let root = document.root();
+
+        let mut img_attrs = HashMap::new();
+        img_attrs.insert(String::from("src"), String::from("a.png"));
+        let mut input_attrs = HashMap::new();
+        input_attrs.insert(String::from("type"), String::from("text"));
+        let expected_body = elem(
+            String::from("body"),
+            HashMap::new(),
+            vec![
+                elem(
+                    String::from("p"),
+                    HashMap::new(),
+                    vec![
+                        text(String::from("one")),
+                        elem(String::from("br"), HashMap::new(), vec![]),
+                        text(String::from("two")),
+                        elem(String::from("img"), img_attrs, vec![]),
+                        elem(String::from("foo"), HashMap::new(), vec![]),
+                        text(String::from("three")),
+                    ],
+                ),
+                elem(String::from("hr"), HashMap::new(), vec![]),
+                elem(String::from("input"), input_attrs, vec![]),
+            ],
+        );
+        assert_eq!(expected_body.root(), root.children().nth(1).unwrap());
+        assert_eq!(Vec::<ParseError>::new(), errors);
+    }
+
+    #[test]
+    fn test_comments_and_doctype() {
+        let source = String::from(
+            r#"<!DOCTYPE html>
+<!-- lead -->
+<html>
+<head></head>
+<body><!--c--><p>x</p><?xml-stylesheet href="a"?></body>
+</html>"#,
+        );
+        let document = parse(source);
+
+        assert_eq!(
+            Some(Doctype {
+                name: String::from("html"),
+                public_id: None,
+                system_id: None,
+            }),
+            document.doctype
+        );
+        assert_eq!(QuirksMode::NoQuirks, document.mode);
+
+        let expected = elem(
+            String::from("html"),
+            HashMap::new(),
+            vec![
+                comment(String::from(" lead ")),
+                elem(String::from("head"), HashMap::new(), vec![]),
+                text(String::from("\n")),
+                elem(
+                    String::from("body"),
+                    HashMap::new(),
+                    vec![
+                        comment(String::from("c")),
+                        elem(
+                            String::from("p"),
+                            HashMap::new(),
+                            vec![text(String::from("x"))],
+                        ),
+                        comment(String::from(r#"?xml-stylesheet href="a"?"#)),
+                        text(String::from("\n")),
+                    ],
+                ),
+            ],
+        );
+        assert_eq!(expected.root(), document.root());
+    }
+
+    #[test]
+    fn test_quirks_mode() {
+        let mode = |source: &str| parse(source.to_string()).mode;
+
+        assert_eq!(QuirksMode::Quirks, mode("<p>no doctype</p>"));
+        assert_eq!(QuirksMode::NoQuirks, mode("<!doctype HTML><p>"));
+        assert_eq!(
+            QuirksMode::Quirks,
+            mode(r#"<!DOCTYPE HTML PUBLIC "-//W3C//DTD HTML 4.01 Transitional//EN"><p>"#)
+        );
+        assert_eq!(
+            QuirksMode::LimitedQuirks,
+            mode(
+                r#"<!DOCTYPE HTML PUBLIC "-//W3C//DTD HTML 4.01 Transitional//EN"
+                "http://www.w3.org/TR/html4/loose.dtd"><p>"#
+            )
+        );
+        assert_eq!(
+            QuirksMode::NoQuirks,
+            mode(
+                r#"<!DOCTYPE HTML PUBLIC "-//W3C//DTD HTML 4.01//EN"
+                "http://www.w3.org/TR/html4/strict.dtd"><p>"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_character_references() {
+        let source = String::from(
+            r#"<p title="a&amp;b &quot;c&quot; ?x=1&copy=2 &copy">&lt;&gt; &nbsp;&#169;&#x1F600;&copy &notit; &#0; &#x80; &#x110000; &bogus; &</p>"#,
+        );
+        let document = parse(source);
+        let root = document.root();
+
+        let p = root.children().nth(1).unwrap().first_child().unwrap();
+        match p.node_type() {
+            NodeType::Element(elem) => assert_eq!(
+                Some(&String::from("a&b \"c\" ?x=1&copy=2 \u{A9}")),
+                elem.attributes.get("title")
+            ),
+            _ => panic!("expected an element"),
+        }
+        let expected = text(String::from(
+            "<> \u{A0}\u{A9}\u{1F600}\u{A9} \u{AC}it; \u{FFFD} \u{20AC} \u{FFFD} &bogus; &",
+        ));
+        assert_eq!(vec![expected.root()], p.children().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_attribute_syntax() {
+        let source = String::from(
+            r#"<DIV Data-Role=main aria-label = 'Menu' xml:lang="en" hidden><INPUT disabled type=checkbox TYPE="text"/></Div>"#,
+        );
+        let (document, errors) = parse_with_errors(source);
+
+        let mut div_attrs = HashMap::new();
+        div_attrs.insert(String::from("data-role"), String::from("main"));
+        div_attrs.insert(String::from("aria-label"), String::from("Menu"));
+        div_attrs.insert(String::from("xml:lang"), String::from("en"));
+        div_attrs.insert(String::from("hidden"), String::new());
+        let mut input_attrs = HashMap::new();
+        input_attrs.insert(String::from("disabled"), String::new());
+        input_attrs.insert(String::from("type"), String::from("checkbox"));
+        let expected = elem(
+            String::from("div"),
+            div_attrs,
+            vec![elem(String::from("input"), input_attrs, vec![])],
+        );
+        let body = document.root().children().nth(1).unwrap();
+        assert_eq!(expected.root(), body.first_child().unwrap());
+        assert_eq!(Vec::<ParseError>::new(), errors);
+
+        // Quotes and `<` in a name, or an `=` at its start, are kept in the name.
+        let (document, errors) = parse_with_errors(String::from(r#"<div <p a"b=1><p =x>"#));
+        let body = document.root().children().nth(1).unwrap();
+        let attribute_names = |node: NodeRef| {
+            let mut names: Vec<String> = node
+                .as_element()
+                .unwrap()
+                .attributes
+                .keys()
+                .cloned()
+                .collect();
+            names.sort();
+            names
+        };
+        let div = body.first_child().unwrap();
+        assert_eq!(vec!["<p", "a\"b"], attribute_names(div));
+        assert_eq!(vec!["=x"], attribute_names(div.first_child().unwrap()));
+        let found: Vec<(usize, &str)> = errors
+            .iter()
+            .filter(|err| err.expected == "an attribute name")
+            .map(|err| (err.offset, &err.found[..]))
+            .collect();
+        assert_eq!(vec![(5, "'<'"), (9, "'\"'"), (17, "'='")], found);
+    }
+
+    #[test]
+    fn test_raw_text_elements() {
+        let source = String::from(
+            r#"<style>a<b { color: red }</style><script>if (a < b) { s = "</div>"; }</SCRIPT >
+<title>A &amp; <b>B</b></title><textarea>  <p>x</p></textarea><xmp></xmp>"#,
+        );
+        let document = parse(source);
+
+        let head = elem(
+            String::from("head"),
+            HashMap::new(),
+            vec![
+                elem(
+                    String::from("style"),
+                    HashMap::new(),
+                    vec![text(String::from("a<b { color: red }"))],
+                ),
+                elem(
+                    String::from("script"),
+                    HashMap::new(),
+                    vec![text(String::from(r#"if (a < b) { s = "</div>"; }"#))],
+                ),
+                text(String::from("\n")),
+                elem(
+                    String::from("title"),
+                    HashMap::new(),
+                    vec![text(String::from("A & <b>B</b>"))],
+                ),
+            ],
+        );
+        let body = elem(
+            String::from("body"),
+            HashMap::new(),
+            vec![
+                elem(
+                    String::from("textarea"),
+                    HashMap::new(),
+                    vec![text(String::from("  <p>x</p>"))],
+                ),
+                elem(String::from("xmp"), HashMap::new(), vec![]),
+            ],
+        );
+        assert_eq!(
+            vec![head.root(), body.root()],
+            document.root().children().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_whitespace_preserved() {
+        let source = String::from("<p><b>a</b> <i>b</i>  </p><pre>\n\n  x\n</pre>");
+        let document = parse(source);
+        let body = document.root().last_child().unwrap();
+
+        let p = body.first_child().unwrap();
+        assert_eq!(
+            vec![
+                String::from("a"),
+                String::from(" "),
+                String::from("b"),
+                String::from("  ")
+            ],
+            p.children()
+                .map(|child| child.text_content())
+                .collect::<Vec<_>>()
+        );
+
+        // Only the first newline after <pre> is dropped.
+        let pre = body.last_child().unwrap();
+        assert_eq!("\n  x\n", pre.text_content());
+    }
+
+    #[test]
+    fn test_source_spans() {
+        let source = String::from("<html>\n<body>\n  <p class=\"x\">Hi <!-- c --></p>\n</body>");
+        let document = parse(source);
+        let root = document.root();
+
+        // <head> was never written, so it has no location.
+        assert_eq!(None, root.first_child().unwrap().span());
+
+        // The body starts with the whitespace text before <p>.
+        let p = root.children().nth(1).unwrap().children().nth(1).unwrap();
+        let span = p.span().unwrap();
+        assert_eq!((3, 3), (span.start.line, span.start.column));
+        assert_eq!((3, 16), (span.end.line, span.end.column));
+
+        let text_span = p.first_child().unwrap().span().unwrap();
+        assert_eq!((3, 16), (text_span.start.line, text_span.start.column));
+        let comment_span = p.last_child().unwrap().span().unwrap();
+        assert_eq!("3:19", comment_span.to_string());
+        assert_eq!(42, comment_span.end.offset);
+    }
+
+    #[test]
+    fn test_unterminated_tag() {
+        // A tag cut off by the end of the input is dropped.
+        let (document, errors) = parse_with_errors(String::from("<p>x<b class=\"a\""));
+        let body = document.root().children().nth(1).unwrap();
+        let p = elem(
+            String::from("p"),
+            HashMap::new(),
+            vec![text(String::from("x"))],
+        );
+        assert_eq!(p.root(), body.first_child().unwrap());
+        assert_eq!(1, errors.len());
+        let err = &errors[0];
+        assert_eq!((err.offset, err.line, err.column), (16, 1, 17));
+        assert_eq!(err.expected, "'>'");
+        assert_eq!(err.found, "end of input");
+
+        let (_, errors) = parse_with_errors(String::from("<a href=\"x"));
+        assert_eq!(1, errors.len());
+        assert_eq!(errors[0].expected, "'\"'");
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let tokens = tokenize("<!doctype html><p class=a>x &amp; y</P><!--c--><br/>");
+        let tokens: Vec<Token> = tokens.into_iter().map(|(token, _)| token).collect();
+
+        let mut attrs = HashMap::new();
+        attrs.insert(String::from("class"), String::from("a"));
+        assert_eq!(
+            vec![
+                Token::Doctype {
+                    doctype: Doctype {
+                        name: String::from("html"),
+                        public_id: None,
+                        system_id: None,
+                    },
+                    force_quirks: false,
+                },
+                Token::StartTag {
+                    name: String::from("p"),
+                    attrs,
+                    self_closing: false,
+                },
+                Token::Text(String::from("x & y")),
+                Token::EndTag {
+                    name: String::from("p"),
+                },
+                Token::Comment(String::from("c")),
+                Token::StartTag {
+                    name: String::from("br"),
+                    attrs: HashMap::new(),
+                    self_closing: true,
+                },
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn test_parse_in_chunks() {
+        let source = r#"<!DOCTYPE html>
+<!-- lead -- -> --><title>A &amp; B</titl></title><script>if (a </ b) {}</scrip</script>
+<p class="x" id='y'>Hello <em>wörld</em>&copy;<br/><textarea>  <b></textarea></p>
+<ul><li>one<li>two</ul><!-- unterminated"#;
+        let (expected, expected_errors) = parse_with_errors(source.to_string());
+
+        // Splitting the input anywhere gives the same result as parsing it all at once.
+        for chunk_size in 1..source.len() {
+            let mut parser = Parser::new();
+            let mut rest = source;
+            while !rest.is_empty() {
+                let mut split = chunk_size.min(rest.len());
+                while !rest.is_char_boundary(split) {
+                    split += 1;
+                }
+                parser.feed(&rest[..split]);
+                rest = &rest[split..];
+            }
+            let (document, errors) = parser.finish();
+            assert_eq!(
+                expected.root(),
+                document.root(),
+                "chunk size {}",
+                chunk_size
+            );
+            assert_eq!(expected.doctype, document.doctype);
+            assert_eq!(expected_errors, errors, "chunk size {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_feed_drops_read_input() {
+        let mut parser = Parser::new();
+        for _ in 0..1000 {
+            parser.feed("<p>Hello, <em>world</em>!</p>\n");
+        }
+        parser.feed("<!-- a long comment, ");
+        assert_eq!(parser.tokenizer.input, "\n<!-- a long comment, ");
+        for _ in 0..1000 {
+            parser.feed("still going, ");
+        }
+        parser.feed("done -->");
+        assert_eq!(
+            parser.tokenizer.location(),
+            Location {
+                offset: 43029,
+                line: 1001,
+                column: 13030
+            }
         );
 
-        assert_eq!(expected, parse(source));
+        let (_, errors) = parser.finish();
+        assert_eq!(errors, vec![]);
     }
 }
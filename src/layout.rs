@@ -1,8 +1,8 @@
+//! Basic CSS block layout.
+
 use crate::css::Unit;
 use crate::css::Value::{Keyword, Length};
-use crate::style::{Display, StyledNode};
-
-///! Basic CSS block layout.
+use crate::style::{self, Display, StyledNode};
 
 // CSS box model. All sizes are in px.
 
@@ -42,6 +42,8 @@ pub struct LayoutBox<'a> {
 pub enum BoxType<'a> {
     BlockNode(&'a StyledNode<'a>),
     InlineNode(&'a StyledNode<'a>),
+    /// A text node and its text after `white-space` processing.
+    TextNode(&'a StyledNode<'a>, String),
     AnonymousBlock,
 }
 
@@ -57,7 +59,7 @@ impl<'a> LayoutBox<'a> {
     /// Where a new inline child should go.
     fn get_inline_container(&mut self) -> &mut LayoutBox<'a> {
         match self.box_type {
-            BoxType::InlineNode(_) | BoxType::AnonymousBlock => self,
+            BoxType::InlineNode(_) | BoxType::TextNode(..) | BoxType::AnonymousBlock => self,
             BoxType::BlockNode(_) => {
                 // If we've just generated an anonymous block box, keep using it.
                 // Otherwise, create a new one.
@@ -78,6 +80,7 @@ impl<'a> LayoutBox<'a> {
         match self.box_type {
             BoxType::BlockNode(_) => self.layout_block(containing_block),
             BoxType::InlineNode(_) => {}  // TODO
+            BoxType::TextNode(..) => {}   // TODO
             BoxType::AnonymousBlock => {} // TODO
         }
     }
@@ -233,7 +236,7 @@ impl<'a> LayoutBox<'a> {
         for child in &mut self.children {
             child.layout(*d);
             // Track the height so each child is laid out below the previous content.
-            d.content.height = d.content.height + child.dimensions.margin_box().height;
+            d.content.height += child.dimensions.margin_box().height;
         }
     }
 
@@ -247,7 +250,9 @@ impl<'a> LayoutBox<'a> {
 
     fn get_style_node(&self) -> &'a StyledNode<'a> {
         match self.box_type {
-            BoxType::BlockNode(node) | BoxType::InlineNode(node) => node,
+            BoxType::BlockNode(node) | BoxType::InlineNode(node) | BoxType::TextNode(node, _) => {
+                node
+            }
             BoxType::AnonymousBlock => panic!("Anonymous block box has no style node"),
         }
     }
@@ -269,6 +274,12 @@ pub fn layout_tree<'a>(
 
 /// Build the tree of LayoutBoxes, but don't perform any layout calculations yet.
 fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
+    build_box(style_node, &mut true)
+}
+
+// `after_space` is true while the inline content built so far is empty or ends in a
+// collapsible space, in which case a collapsible space at the start of the next text is dropped.
+fn build_box<'a>(style_node: &'a StyledNode<'a>, after_space: &mut bool) -> LayoutBox<'a> {
     // Create the root box.
     let mut root = LayoutBox::new(match style_node.display() {
         Display::Block => BoxType::BlockNode(style_node),
@@ -278,18 +289,45 @@ fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
 
     // Create the descendant boxes.
     for child in &style_node.children {
+        if let Some(text) = child.text() {
+            if let Some(text_box) = build_text_box(child, text, after_space) {
+                root.get_inline_container().children.push(text_box);
+            }
+            continue;
+        }
         match child.display() {
-            Display::Block => root.children.push(build_layout_tree(child)),
-            Display::Inline => root
-                .get_inline_container()
-                .children
-                .push(build_layout_tree(child)),
+            Display::Block => {
+                root.children.push(build_box(child, &mut true));
+                *after_space = true;
+            }
+            Display::Inline => {
+                let child_box = build_box(child, after_space);
+                root.get_inline_container().children.push(child_box);
+            }
             Display::None => {} // Skip nodess with `display: none;`
         }
     }
     root
 }
 
+// Build the box for a text node, or nothing if all of its text collapses away.
+fn build_text_box<'a>(
+    style_node: &'a StyledNode<'a>,
+    text: &str,
+    after_space: &mut bool,
+) -> Option<LayoutBox<'a>> {
+    let white_space = style_node.white_space();
+    let mut text = style::collapse_white_space(text, white_space);
+    if white_space.collapses_spaces() && *after_space && text.starts_with(' ') {
+        text.remove(0);
+    }
+    if text.is_empty() {
+        return None;
+    }
+    *after_space = white_space.collapses_spaces() && text.ends_with([' ', '\n']);
+    Some(LayoutBox::new(BoxType::TextNode(style_node, text)))
+}
+
 impl Dimensions {
     /// The area covered by the content area plus its padding.
     fn padding_box(self) -> Rect {
@@ -324,3 +362,124 @@ where
 {
     iter.fold(0., |a, b| a + b)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{layout_tree, BoxType, Dimensions, LayoutBox};
+    use crate::css::{self, PseudoElement};
+    use crate::html;
+    use crate::style::{self, StyledNode};
+
+    // Style `source` inside an `<html>` element with `css`, and pass the styled root to `f`.
+    fn with_style_tree<T>(source: &str, css: &str, f: impl FnOnce(&StyledNode) -> T) -> T {
+        let document = html::parse(format!("<html>{}</html>", source));
+        let stylesheet = css::parse(css.to_string());
+        f(&style::style_tree(document.root(), &stylesheet))
+    }
+
+    fn viewport() -> Dimensions {
+        let mut viewport: Dimensions = Default::default();
+        viewport.content.width = 800.0;
+        viewport
+    }
+
+    // The text of each text box below `layout_box`, in order.
+    fn text_boxes(layout_box: &LayoutBox) -> Vec<String> {
+        let mut texts = vec![];
+        if let BoxType::TextNode(_, text) = &layout_box.box_type {
+            texts.push(text.clone());
+        }
+        for child in &layout_box.children {
+            texts.extend(text_boxes(child));
+        }
+        texts
+    }
+
+    #[test]
+    fn test_white_space_between_boxes() {
+        let texts = |source: &str| {
+            let css = "html, body, div, p { display: block; } head { display: none; } pre { white-space: pre; }";
+            with_style_tree(source, css, |root| {
+                text_boxes(&layout_tree(root, viewport()))
+            })
+        };
+
+        // A space between inline elements is kept, but only once.
+        assert_eq!(vec!["a", " ", "b"], texts("<b>a</b> <i>b</i>"));
+        assert_eq!(vec!["a ", "b"], texts("<b>a </b> <i> b</i>"));
+
+        // Spaces at the start of a line, after a block or at the start of one, are dropped.
+        assert_eq!(vec!["a", "b ", "c"], texts("<p>a</p>   b <p> c</p>"));
+        assert_eq!(vec!["a", "b"], texts("<div>a<p>\n  b</p></div>"));
+
+        // Preformatted text keeps its spaces, and doesn't swallow the spaces after it.
+        assert_eq!(
+            vec!["a ", " b  c", " d"],
+            texts("<b>a </b><pre> b  c</pre> d")
+        );
+        assert_eq!(vec!["a ", " b"], texts("<pre>a </pre> b"));
+    }
+
+    #[test]
+    fn test_generated_content_boxes() {
+        let css = r#"
+            html, body, p, div { display: block; }
+            head { display: none; }
+            p::before { content: "1."; display: block; height: 20px; }
+            p::after { content: "!"; display: block; height: 10px; }
+            em::before { content: "("; }
+            em::after { content: ")"; }
+        "#;
+        with_style_tree("<p>Hi</p><div>a <em>x</em></div>", css, |root| {
+            let root = layout_tree(root, viewport());
+            assert_eq!(
+                vec!["1.", "Hi", "!", "a ", "(", "x", ")"],
+                text_boxes(&root)
+            );
+
+            // Block pseudo-elements are the first and last blocks inside their element.
+            let body = &root.children[0];
+            let p = &body.children[0];
+            let [before, text, after] = &p.children[..] else {
+                panic!("expected three boxes in <p>");
+            };
+            assert!(matches!(before.box_type, BoxType::BlockNode(node)
+                if node.pseudo_element == Some(PseudoElement::Before)));
+            assert!(matches!(text.box_type, BoxType::AnonymousBlock));
+            assert!(matches!(after.box_type, BoxType::BlockNode(node)
+                if node.pseudo_element == Some(PseudoElement::After)));
+            assert_eq!(
+                (0.0, 20.0),
+                (
+                    before.dimensions.content.y,
+                    before.dimensions.content.height
+                )
+            );
+            assert_eq!(
+                (20.0, 10.0),
+                (after.dimensions.content.y, after.dimensions.content.height)
+            );
+            assert_eq!(30.0, p.dimensions.content.height);
+            assert_eq!(30.0, body.children[1].dimensions.content.y);
+
+            // Inline pseudo-elements sit around the element's own content.
+            let em = &body.children[1].children[0].children[1];
+            let pseudo_elements: Vec<_> = em
+                .children
+                .iter()
+                .map(|child| match child.box_type {
+                    BoxType::InlineNode(node) => node.pseudo_element,
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(
+                vec![
+                    Some(PseudoElement::Before),
+                    None,
+                    Some(PseudoElement::After)
+                ],
+                pseudo_elements
+            );
+        });
+    }
+}
@@ -0,0 +1,376 @@
+//! Loading of external resources, such as stylesheets, that a document refers to.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use crate::css::{self, Stylesheet};
+use crate::dom::{NodeRef, NodeType};
+use crate::encoding;
+use crate::source::Location;
+
+/// Reads the resources referenced by a document.
+pub trait ResourceLoader {
+    /// Return the contents of the resource at `path`, which may be in any character encoding.
+    fn load(&self, path: &Path) -> io::Result<Vec<u8>>;
+}
+
+/// Reads resources from the local filesystem.
+pub struct FileLoader;
+
+impl ResourceLoader for FileLoader {
+    fn load(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+}
+
+/// A resource that couldn't be loaded.
+#[derive(Debug)]
+pub struct LoadError {
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.error)
+    }
+}
+
+/// An error in a stylesheet that the CSS parser recovered from.
+#[derive(Debug)]
+pub struct StylesheetError {
+    /// The stylesheet, or for a `<style>` element the document containing it.
+    pub path: PathBuf,
+    pub error: css::ParseError,
+}
+
+impl fmt::Display for StylesheetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.path.display(), self.error)
+    }
+}
+
+/// Resolve `href` relative to the resource at `base`.
+pub fn resolve(base: &Path, href: &str) -> Result<PathBuf, LoadError> {
+    // Query strings and fragments don't name different files on disk.
+    let href = href.split(['?', '#']).next().unwrap_or("");
+    let href = match href.strip_prefix("file://") {
+        Some(path) => path,
+        None if href.contains("://") => {
+            return Err(LoadError {
+                path: PathBuf::from(href),
+                error: io::Error::new(io::ErrorKind::Unsupported, "only local files are supported"),
+            })
+        }
+        None => href,
+    };
+
+    let path = match base.parent() {
+        Some(dir) => dir.join(href),
+        None => PathBuf::from(href),
+    };
+    Ok(normalize(&path))
+}
+
+/// Remove `.` and `..` components from a path without touching the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !result.pop() {
+                    result.push("..");
+                }
+            }
+            _ => result.push(component),
+        }
+    }
+    result
+}
+
+/// Loads stylesheets along with the stylesheets they `@import`, recording any that fail.
+pub struct StylesheetLoader<'a> {
+    loader: &'a dyn ResourceLoader,
+    // Stylesheets that are currently being loaded, to break `@import` cycles.
+    loading: Vec<PathBuf>,
+    pub errors: Vec<LoadError>,
+    pub parse_errors: Vec<StylesheetError>,
+}
+
+impl<'a> StylesheetLoader<'a> {
+    pub fn new(loader: &'a dyn ResourceLoader) -> StylesheetLoader<'a> {
+        StylesheetLoader {
+            loader,
+            loading: vec![],
+            errors: vec![],
+            parse_errors: vec![],
+        }
+    }
+
+    /// Load and parse the stylesheet at `path`. Returns `None` if it can't be read.
+    pub fn load(&mut self, path: &Path) -> Option<Stylesheet> {
+        if self.loading.iter().any(|loading| loading == path) {
+            return None;
+        }
+        match self.loader.load(path) {
+            Ok(bytes) => {
+                let (source, _) = encoding::decode_css(&bytes);
+                self.loading.push(path.to_path_buf());
+                let stylesheet = self.parse(source, path);
+                self.loading.pop();
+                Some(stylesheet)
+            }
+            Err(error) => {
+                self.errors.push(LoadError {
+                    path: path.to_path_buf(),
+                    error,
+                });
+                None
+            }
+        }
+    }
+
+    /// Parse a stylesheet, resolving its `@import` rules relative to `path`. Each rule records
+    /// the file it came from.
+    pub fn parse(&mut self, source: String, path: &Path) -> Stylesheet {
+        self.parse_embedded(source, path, None)
+    }
+
+    // Parse a stylesheet as `parse` does. If it is embedded in the file at `path`, as the text of
+    // a `<style>` element is, `origin` is where it starts, so that its spans and errors point
+    // into that file.
+    fn parse_embedded(
+        &mut self,
+        source: String,
+        path: &Path,
+        origin: Option<Location>,
+    ) -> Stylesheet {
+        let (mut stylesheet, errors) =
+            css::parse_with_errors(source, |url| match resolve(path, url) {
+                Ok(import_path) => self.load(&import_path),
+                Err(err) => {
+                    self.errors.push(err);
+                    None
+                }
+            });
+        self.parse_errors
+            .extend(errors.into_iter().map(|mut error| {
+                if let Some(origin) = origin {
+                    let location = Location {
+                        offset: error.offset,
+                        line: error.line,
+                        column: error.column,
+                    }
+                    .offset_from(origin);
+                    (error.offset, error.line, error.column) =
+                        (location.offset, location.line, location.column);
+                }
+                StylesheetError {
+                    path: path.to_path_buf(),
+                    error,
+                }
+            }));
+
+        // Imported rules already have the path of their own file, and spans counted from its
+        // start.
+        let rule_path: Rc<Path> = Rc::from(path);
+        for rule in &mut stylesheet.rules {
+            if rule.path.is_some() {
+                continue;
+            }
+            rule.path = Some(rule_path.clone());
+            if let Some(origin) = origin {
+                rule.span = rule.span.map(|span| span.offset_from(origin));
+                for declaration in &mut rule.declarations {
+                    declaration.span = declaration.span.map(|span| span.offset_from(origin));
+                }
+            }
+        }
+        stylesheet
+    }
+
+    /// Collect the stylesheets of the document at `document_path`, from its
+    /// `<link rel="stylesheet">` and `<style>` elements in document order.
+    pub fn document_stylesheets(&mut self, root: NodeRef, document_path: &Path) -> Vec<Stylesheet> {
+        let mut stylesheets = vec![];
+        self.collect_stylesheets(root, document_path, &mut stylesheets);
+        stylesheets
+    }
+
+    fn collect_stylesheets(
+        &mut self,
+        node: NodeRef,
+        document_path: &Path,
+        stylesheets: &mut Vec<Stylesheet>,
+    ) {
+        match node.node_type() {
+            NodeType::Element(elem) if elem.tag_name == "style" => {
+                let source = node
+                    .children()
+                    .filter_map(|child| match child.node_type() {
+                        NodeType::Text(text) => Some(&text[..]),
+                        _ => None,
+                    })
+                    .collect();
+                let origin = node
+                    .first_child()
+                    .and_then(|child| child.span())
+                    .map(|span| span.start);
+                stylesheets.push(self.parse_embedded(source, document_path, origin));
+            }
+            NodeType::Element(elem) if elem.tag_name == "link" => {
+                let rel = elem.attributes.get("rel").map_or("", |rel| &rel[..]);
+                let is_stylesheet = rel
+                    .split_ascii_whitespace()
+                    .any(|keyword| keyword.eq_ignore_ascii_case("stylesheet"));
+                let is_alternate = rel
+                    .split_ascii_whitespace()
+                    .any(|keyword| keyword.eq_ignore_ascii_case("alternate"));
+                if let (true, false, Some(href)) =
+                    (is_stylesheet, is_alternate, elem.attributes.get("href"))
+                {
+                    match resolve(document_path, href) {
+                        Ok(path) => stylesheets.extend(self.load(&path)),
+                        Err(err) => self.errors.push(err),
+                    }
+                }
+            }
+            _ => {
+                for child in node.children() {
+                    self.collect_stylesheets(child, document_path, stylesheets);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    use super::{resolve, ResourceLoader, StylesheetLoader};
+    use crate::css;
+    use crate::html;
+
+    /// Serves files from memory instead of the filesystem.
+    struct MemoryLoader(HashMap<PathBuf, String>);
+
+    impl ResourceLoader for MemoryLoader {
+        fn load(&self, path: &Path) -> io::Result<Vec<u8>> {
+            self.0
+                .get(path)
+                .map(|source| source.clone().into_bytes())
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let base = Path::new("site/pages/index.html");
+        assert_eq!(
+            PathBuf::from("site/pages/main.css"),
+            resolve(base, "./main.css?v=2").unwrap()
+        );
+        assert_eq!(
+            PathBuf::from("site/css/main.css"),
+            resolve(base, "../css/main.css").unwrap()
+        );
+        assert_eq!(
+            PathBuf::from("/srv/main.css"),
+            resolve(base, "file:///srv/main.css").unwrap()
+        );
+        assert!(resolve(base, "https://example.com/main.css").is_err());
+    }
+
+    #[test]
+    fn test_document_stylesheets() {
+        let mut files = HashMap::new();
+        files.insert(
+            PathBuf::from("site/css/a.css"),
+            String::from("@import 'b.css'; a { display: block; }"),
+        );
+        files.insert(
+            PathBuf::from("site/css/b.css"),
+            String::from("@import url(a.css); b { display: block; float: left right; }"),
+        );
+        let loader = MemoryLoader(files);
+
+        let document = html::parse(String::from(
+            r#"<link rel="stylesheet" href="css/a.css">
+<style>c { display: block; float: left right; }
+  d { display: block; }</style>
+<link rel="alternate stylesheet" href="css/b.css">
+<link rel="Stylesheet" href="missing.css">"#,
+        ));
+
+        let mut stylesheet_loader = StylesheetLoader::new(&loader);
+        let stylesheets =
+            stylesheet_loader.document_stylesheets(document.root(), Path::new("site/index.html"));
+
+        // The import cycle from b.css back to a.css is ignored.
+        let expected = vec![
+            css::parse(String::from("b { display: block; } a { display: block; }")),
+            css::parse(String::from("c { display: block; } d { display: block; }")),
+        ];
+        assert_eq!(expected, stylesheets);
+        let rule_paths: Vec<Vec<&Path>> = stylesheets
+            .iter()
+            .map(|stylesheet| {
+                stylesheet
+                    .rules
+                    .iter()
+                    .filter_map(|rule| rule.path.as_deref())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                vec![Path::new("site/css/b.css"), Path::new("site/css/a.css")],
+                vec![Path::new("site/index.html"), Path::new("site/index.html")],
+            ],
+            rule_paths
+        );
+        let rule = &stylesheets[0].rules[1];
+        assert_eq!(
+            "site/css/a.css:1:22",
+            format!(
+                "{}:{}",
+                rule.path.as_ref().unwrap().display(),
+                rule.declarations[0].span.unwrap()
+            )
+        );
+        // Spans in a `<style>` element are counted from the start of the document.
+        assert_eq!("2:8", stylesheets[1].rules[0].span.unwrap().to_string());
+        assert_eq!("3:3", stylesheets[1].rules[1].span.unwrap().to_string());
+        assert_eq!(
+            "3:7",
+            stylesheets[1].rules[1].declarations[0]
+                .span
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(1, stylesheet_loader.errors.len());
+        assert_eq!(
+            PathBuf::from("site/missing.css"),
+            stylesheet_loader.errors[0].path
+        );
+        let parse_errors: Vec<String> = stylesheet_loader
+            .parse_errors
+            .iter()
+            .map(|err| err.to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                "site/css/b.css:1:48: expected a supported value, found 'left right'",
+                "site/index.html:2:35: expected a supported value, found 'left right'",
+            ],
+            parse_errors
+        );
+    }
+}
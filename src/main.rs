@@ -1,15 +1,21 @@
 use core::panic;
-use std::fs::File;
-use std::io::Read;
+use std::fs;
+use std::path::Path;
 
 extern crate getopts;
 extern crate image;
 
 pub mod css;
 pub mod dom;
+pub mod encoding;
+pub mod entities;
 pub mod html;
 pub mod layout;
+pub mod loader;
 pub mod painting;
+pub mod query;
+pub mod serialize;
+pub mod source;
 pub mod style;
 
 fn main() {
@@ -33,8 +39,9 @@ fn main() {
     };
 
     // Read input files:
-    let html = read_source(str_arg("h", "examples/test.html"));
-    let css = read_source(str_arg("c", "examples/test.css"));
+    let html_filename = str_arg("h", "examples/test.html");
+    let (html, _) = encoding::decode_html(&read_source(&html_filename));
+    let css_filename = matches.opt_str("c");
 
     // Since we don't have an actual window, hard-code the "viewport" size.
     let mut viewport: layout::Dimensions = Default::default();
@@ -42,9 +49,34 @@ fn main() {
     viewport.content.height = 600.0;
 
     // Parsing and rendering:
-    let root_node = html::parse(html);
-    let stylesheet = css::parse(css);
-    let style_root = style::style_tree(&root_node, &stylesheet);
+    let (document, errors) = html::parse_with_errors(html);
+    for err in errors {
+        eprintln!("{}: warning: {}", html_filename, err);
+    }
+
+    // Combine the stylesheet given on the command line, if any, with those the document links
+    // to or embeds.
+    let mut loader = loader::StylesheetLoader::new(&loader::FileLoader);
+    let mut stylesheet = match &css_filename {
+        Some(css_filename) => {
+            let (source, _) = encoding::decode_css(&read_source(css_filename));
+            loader.parse(source, Path::new(css_filename))
+        }
+        None => css::Stylesheet { rules: vec![] },
+    };
+    for document_stylesheet in
+        loader.document_stylesheets(document.root(), Path::new(&html_filename))
+    {
+        stylesheet.rules.extend(document_stylesheet.rules);
+    }
+    for err in &loader.errors {
+        eprintln!("warning: {}", err);
+    }
+    for err in &loader.parse_errors {
+        eprintln!("warning: {}", err);
+    }
+
+    let style_root = style::style_tree(document.root(), &stylesheet);
     let layout_root = layout::layout_tree(&style_root, viewport);
 
     // Create the output file:
@@ -71,11 +103,6 @@ fn main() {
     }
 }
 
-fn read_source(filename: String) -> String {
-    let mut str = String::new();
-    File::open(filename)
-        .unwrap()
-        .read_to_string(&mut str)
-        .unwrap();
-    str
+fn read_source(filename: &str) -> Vec<u8> {
+    fs::read(filename).unwrap()
 }
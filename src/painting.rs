@@ -26,12 +26,12 @@ fn render_layout_box(list: &mut DisplayList, layout_box: &LayoutBox) {
 }
 
 fn render_background(list: &mut DisplayList, layout_box: &LayoutBox) {
-    get_color(layout_box, "background").map(|color| {
+    if let Some(color) = get_color(layout_box, "background") {
         list.push(DisplayCommand::SolidColor(
             color,
             layout_box.dimensions.border_box(),
         ))
-    });
+    }
 }
 
 fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox) {
@@ -95,7 +95,7 @@ fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
             Some(Value::ColorValue(color)) => Some(color),
             _ => None,
         },
-        BoxType::AnonymousBlock => None,
+        BoxType::TextNode(..) | BoxType::AnonymousBlock => None,
     }
 }
 
@@ -148,5 +148,5 @@ pub fn paint(layout_root: &LayoutBox, bounds: Rect) -> Canvas {
     for item in display_list {
         canvas.paint_item(&item);
     }
-    return canvas;
+    canvas
 }
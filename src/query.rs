@@ -0,0 +1,97 @@
+//! Finding elements in a DOM tree with CSS selectors, like `querySelector` and
+//! `querySelectorAll`.
+
+use crate::css;
+use crate::dom::NodeRef;
+use crate::style;
+
+/// Return the first element below `root`, in document order, that matches any of the
+/// comma-separated `selectors`. Fails if `selectors` is invalid, where `querySelector` would
+/// throw a `SyntaxError`.
+pub fn query_selector<'a>(
+    root: NodeRef<'a>,
+    selectors: &str,
+) -> Result<Option<NodeRef<'a>>, css::ParseError> {
+    let selectors = css::parse_selector_list(selectors.to_string())?;
+    Ok(root
+        .pre_order()
+        .skip(1)
+        .find(|node| matches_any(*node, &selectors)))
+}
+
+/// Return all elements below `root`, in document order, that match any of the comma-separated
+/// `selectors`. `root` itself is never included. Fails if `selectors` is invalid.
+pub fn query_selector_all<'a>(
+    root: NodeRef<'a>,
+    selectors: &str,
+) -> Result<Vec<NodeRef<'a>>, css::ParseError> {
+    let selectors = css::parse_selector_list(selectors.to_string())?;
+    Ok(root
+        .pre_order()
+        .skip(1)
+        .filter(|node| matches_any(*node, &selectors))
+        .collect())
+}
+
+/// Is `node` an element that matches one of `selectors`?
+fn matches_any(node: NodeRef, selectors: &[css::Selector]) -> bool {
+    node.as_element().is_some()
+        && selectors
+            .iter()
+            .any(|selector| style::matches(node, selector))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{query_selector, query_selector_all};
+    use crate::dom::NodeRef;
+    use crate::html;
+
+    #[test]
+    fn test_query_selector() {
+        let document = html::parse(String::from(
+            r#"<div id="main" class="a"><p class="a b">One</p><P>Two</P></div><p id="x">Three"#,
+        ));
+        let root = document.root();
+        let tag_names = |nodes: Vec<NodeRef>| -> Vec<String> {
+            nodes
+                .into_iter()
+                .map(|node| node.as_element().unwrap().tag_name.clone())
+                .collect()
+        };
+
+        assert_eq!(
+            vec!["p", "p", "p"],
+            tag_names(query_selector_all(root, "p").unwrap())
+        );
+        assert_eq!(
+            vec!["div", "p"],
+            tag_names(query_selector_all(root, ".a").unwrap())
+        );
+        assert_eq!(
+            vec!["div", "p"],
+            tag_names(query_selector_all(root, " #x, #main ").unwrap())
+        );
+        assert_eq!(
+            vec!["p"],
+            tag_names(query_selector_all(root, "p.a.b").unwrap())
+        );
+        assert!(query_selector_all(root, "span").unwrap().is_empty());
+
+        let first = query_selector(root, "p").unwrap().unwrap();
+        assert_eq!(
+            Some("a b"),
+            first
+                .as_element()
+                .unwrap()
+                .attributes
+                .get("class")
+                .map(|c| &c[..])
+        );
+        assert!(query_selector(root, "html").unwrap().is_none());
+
+        let error = query_selector(root, "p >").unwrap_err();
+        assert_eq!((3, "end of input"), (error.offset, &error.found[..]));
+        assert!(query_selector_all(root, "p, ").is_err());
+    }
+}
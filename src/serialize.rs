@@ -0,0 +1,280 @@
+//! Serialization of DOM trees back to HTML.
+//!
+//! This follows the HTML fragment serialization algorithm:
+//! https://html.spec.whatwg.org/multipage/parsing.html#serialising-html-fragments
+
+use crate::dom::{Document, ElementData, NodeRef, NodeType};
+use crate::html::VOID_ELEMENTS;
+
+/// How serialized markup is laid out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Exactly the markup the tree represents, suitable for parsing again.
+    Compact,
+    /// One node per line, indented by depth. Whitespace around text is trimmed, except inside
+    /// elements like `<pre>` where it is significant.
+    Pretty,
+}
+
+// Elements whose text contents are written without escaping.
+const RAW_TEXT_ELEMENTS: &[&str] = &[
+    "iframe",
+    "noembed",
+    "noframes",
+    "plaintext",
+    "script",
+    "style",
+    "xmp",
+];
+
+// Elements whose contents keep their whitespace when pretty-printing.
+const PREFORMATTED_ELEMENTS: &[&str] = &["listing", "pre", "textarea"];
+
+/// Serialize a node and its descendants.
+pub fn serialize(node: NodeRef, format: Format) -> String {
+    let mut out = String::new();
+    match format {
+        Format::Compact => write_node(&mut out, node, None),
+        Format::Pretty => write_pretty(&mut out, node, None, 0),
+    }
+    out
+}
+
+/// Serialize a whole document, including its doctype.
+pub fn serialize_document(document: &Document, format: Format) -> String {
+    let mut out = String::new();
+    if let Some(ref doctype) = document.doctype {
+        out.push_str("<!DOCTYPE ");
+        out.push_str(&doctype.name);
+        match (&doctype.public_id, &doctype.system_id) {
+            (Some(public_id), Some(system_id)) => {
+                out.push_str(&format!(" PUBLIC \"{}\" \"{}\"", public_id, system_id))
+            }
+            (Some(public_id), None) => out.push_str(&format!(" PUBLIC \"{}\"", public_id)),
+            (None, Some(system_id)) => out.push_str(&format!(" SYSTEM \"{}\"", system_id)),
+            (None, None) => {}
+        }
+        out.push('>');
+        if format == Format::Pretty {
+            out.push('\n');
+        }
+    }
+    out.push_str(&serialize(document.root(), format));
+    out
+}
+
+fn write_node(out: &mut String, node: NodeRef, parent: Option<&ElementData>) {
+    match node.node_type() {
+        NodeType::Element(elem) => {
+            write_start_tag(out, node, elem);
+            if !is_void(elem) {
+                for child in node.children() {
+                    write_node(out, child, Some(elem));
+                }
+                write_end_tag(out, elem);
+            }
+        }
+        NodeType::Text(text) => write_text(out, text, parent),
+        NodeType::Comment(data) => {
+            out.push_str("<!--");
+            out.push_str(data);
+            out.push_str("-->");
+        }
+    }
+}
+
+fn write_pretty(out: &mut String, node: NodeRef, parent: Option<&ElementData>, depth: usize) {
+    let indent = "  ".repeat(depth);
+    match node.node_type() {
+        NodeType::Element(elem) => {
+            out.push_str(&indent);
+            write_start_tag(out, node, elem);
+            if is_void(elem) {
+                out.push('\n');
+                return;
+            }
+
+            // Elements that contain only text stay on one line, as do those whose whitespace
+            // matters.
+            let inline = is_one_of(&elem.tag_name, RAW_TEXT_ELEMENTS)
+                || is_one_of(&elem.tag_name, PREFORMATTED_ELEMENTS)
+                || node
+                    .children()
+                    .all(|child| matches!(child.node_type(), NodeType::Text(_)));
+            if inline {
+                for child in node.children() {
+                    write_node(out, child, Some(elem));
+                }
+            } else {
+                out.push('\n');
+                for child in node.children() {
+                    write_pretty(out, child, Some(elem), depth + 1);
+                }
+                out.push_str(&indent);
+            }
+            write_end_tag(out, elem);
+            out.push('\n');
+        }
+        NodeType::Text(text) => {
+            let text = text.trim();
+            if !text.is_empty() {
+                out.push_str(&indent);
+                write_text(out, text, parent);
+                out.push('\n');
+            }
+        }
+        NodeType::Comment(_) => {
+            out.push_str(&indent);
+            write_node(out, node, parent);
+            out.push('\n');
+        }
+    }
+}
+
+fn write_text(out: &mut String, text: &str, parent: Option<&ElementData>) {
+    if parent.is_some_and(|parent| is_one_of(&parent.tag_name, RAW_TEXT_ELEMENTS)) {
+        out.push_str(text);
+    } else {
+        out.push_str(&escape(text, false));
+    }
+}
+
+fn write_start_tag(out: &mut String, node: NodeRef, elem: &ElementData) {
+    out.push('<');
+    out.push_str(&elem.tag_name);
+
+    // Attributes are written in name order so that the output is deterministic.
+    let mut attributes: Vec<_> = elem.attributes.iter().collect();
+    attributes.sort();
+    for (name, value) in attributes {
+        out.push(' ');
+        out.push_str(name);
+        out.push_str("=\"");
+        out.push_str(&escape(value, true));
+        out.push('"');
+    }
+    out.push('>');
+
+    // The parser drops a newline straight after the start tag of these elements, so a newline
+    // at the start of their text needs another in front of it to survive.
+    let starts_with_newline = node.first_child().is_some_and(
+        |child| matches!(child.node_type(), NodeType::Text(text) if text.starts_with('\n')),
+    );
+    if is_one_of(&elem.tag_name, PREFORMATTED_ELEMENTS) && starts_with_newline {
+        out.push('\n');
+    }
+}
+
+fn write_end_tag(out: &mut String, elem: &ElementData) {
+    out.push_str("</");
+    out.push_str(&elem.tag_name);
+    out.push('>');
+}
+
+/// Escape text or an attribute value.
+/// https://html.spec.whatwg.org/multipage/parsing.html#escapingString
+fn escape(text: &str, attribute_mode: bool) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '\u{A0}' => result.push_str("&nbsp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' if attribute_mode => result.push_str("&quot;"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+fn is_void(elem: &ElementData) -> bool {
+    is_one_of(&elem.tag_name, VOID_ELEMENTS)
+}
+
+fn is_one_of(name: &str, names: &[&str]) -> bool {
+    names.contains(&name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{serialize, serialize_document, Format};
+    use crate::dom::{comment, elem, text};
+    use crate::html;
+
+    #[test]
+    fn test_serialize() {
+        let mut attrs = HashMap::new();
+        attrs.insert(String::from("title"), String::from("\"a\" & <b>"));
+        attrs.insert(String::from("class"), String::from("x"));
+        let node = elem(
+            String::from("div"),
+            attrs,
+            vec![
+                text(String::from("1 < 2 & 3\u{A0}")),
+                elem(String::from("br"), HashMap::new(), vec![]),
+                elem(
+                    String::from("script"),
+                    HashMap::new(),
+                    vec![text(String::from("if (a < b) {}"))],
+                ),
+                comment(String::from(" note ")),
+            ],
+        );
+
+        assert_eq!(
+            r#"<div class="x" title="&quot;a&quot; &amp; &lt;b&gt;">1 &lt; 2 &amp; 3&nbsp;<br><script>if (a < b) {}</script><!-- note --></div>"#,
+            serialize(node.root(), Format::Compact)
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let source = String::from(
+            r#"<!DOCTYPE html>
+<!-- lead -->
+<title>A &amp; B</title>
+<style>p > a { color: red }</style>
+<ul class="list"><li>One<li>Two &lt;2&gt;</ul>
+<p>Hello <em>world</em><br><input disabled value="&quot;x&quot;">
+<textarea>  <b>raw</b></textarea>
+<pre>
+
+foo</pre><listing>
+x</listing>"#,
+        );
+        let document = html::parse(source);
+
+        let serialized = serialize_document(&document, Format::Compact);
+        assert!(serialized.starts_with("<!DOCTYPE html><html><!-- lead --><head><title>"));
+        let reparsed = html::parse(serialized);
+        assert_eq!(document.doctype, reparsed.doctype);
+        assert_eq!(document.root(), reparsed.root());
+    }
+
+    #[test]
+    fn test_pretty() {
+        let document = html::parse(String::from(
+            "<div><p>Hello <em>world</em></p><pre>a\n  b</pre><hr></div>",
+        ));
+
+        let body = document.root().children().nth(1).unwrap();
+        assert_eq!(
+            r#"<body>
+  <div>
+    <p>
+      Hello
+      <em>world</em>
+    </p>
+    <pre>a
+  b</pre>
+    <hr>
+  </div>
+</body>
+"#,
+            serialize(body, Format::Pretty)
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Positions in source text, shared by the HTML and CSS parsers.
+
+use std::fmt;
+
+/// A position in a source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Location {
+    /// Byte offset from the start of the source.
+    pub offset: usize,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, counted in characters.
+    pub column: usize,
+}
+
+impl Location {
+    /// The location in an enclosing source of this location in a text embedded in it, such as
+    /// the contents of a `<style>` element, given that the embedded text starts at `origin`.
+    pub fn offset_from(self, origin: Location) -> Location {
+        Location {
+            offset: origin.offset + self.offset,
+            line: origin.line + self.line - 1,
+            column: if self.line == 1 {
+                origin.column + self.column - 1
+            } else {
+                self.column
+            },
+        }
+    }
+}
+
+/// The range of source text that a node, rule or declaration was parsed from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: Location,
+    pub end: Location,
+}
+
+impl Span {
+    /// The span in an enclosing source of this span in a text embedded in it. See
+    /// `Location::offset_from`.
+    pub fn offset_from(self, origin: Location) -> Span {
+        Span {
+            start: self.start.offset_from(origin),
+            end: self.end.offset_from(origin),
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.start.line, self.start.column)
+    }
+}
+
+/// Maps byte offsets in a source text to line and column numbers.
+pub struct LineIndex {
+    // Byte offset of the start of each line.
+    line_starts: Vec<usize>,
+    // Byte offset just past each multi-byte character, with the number of bytes beyond the first
+    // in it and all earlier ones. This lets columns be counted in characters without scanning
+    // the text, which would be quadratic in the length of a line.
+    multi_byte_chars: Vec<(usize, usize)>,
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> LineIndex {
+        let mut index = LineIndex {
+            line_starts: vec![0],
+            multi_byte_chars: vec![],
+        };
+        index.extend(0, source);
+        index
+    }
+
+    /// Record the line breaks in `text`, which has been appended to the source at byte `offset`.
+    pub fn extend(&mut self, offset: usize, text: &str) {
+        for (i, c) in text.char_indices() {
+            if c == '\n' {
+                self.line_starts.push(offset + i + 1);
+            } else if c.len_utf8() > 1 {
+                let extra = self.extra_bytes_before(usize::MAX) + c.len_utf8() - 1;
+                self.multi_byte_chars
+                    .push((offset + i + c.len_utf8(), extra));
+            }
+        }
+    }
+
+    /// The location of byte `offset` in the source.
+    pub fn location(&self, offset: usize) -> Location {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1,
+        };
+        let line_start = self.line_starts[line];
+        let extra = self.extra_bytes_before(offset) - self.extra_bytes_before(line_start);
+        Location {
+            offset,
+            line: line + 1,
+            column: offset - line_start - extra + 1,
+        }
+    }
+
+    /// The span between two byte offsets in the source.
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        Span {
+            start: self.location(start),
+            end: self.location(end),
+        }
+    }
+
+    /// The number of bytes beyond the first in the multi-byte characters before `offset`.
+    fn extra_bytes_before(&self, offset: usize) -> usize {
+        let count = self
+            .multi_byte_chars
+            .partition_point(|&(end, _)| end <= offset);
+        count
+            .checked_sub(1)
+            .map_or(0, |i| self.multi_byte_chars[i].1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LineIndex, Location};
+
+    #[test]
+    fn test_location() {
+        let source = "ab\nçd\n\nef";
+        let lines = LineIndex::new(source);
+        let location = |offset| lines.location(offset);
+
+        assert_eq!(
+            Location {
+                offset: 0,
+                line: 1,
+                column: 1,
+            },
+            location(0)
+        );
+        assert_eq!(
+            Location {
+                offset: 5,
+                line: 2,
+                column: 2,
+            },
+            location(5)
+        );
+        assert_eq!((3, 1), (location(7).line, location(7).column));
+        assert_eq!((4, 2), (location(9).line, location(9).column));
+    }
+
+    #[test]
+    fn test_location_in_appended_text() {
+        let mut lines = LineIndex::new("é");
+        lines.extend(2, "xé\nüy");
+        lines.extend(9, "ßz");
+        assert_eq!((1, 2), (lines.location(2).line, lines.location(2).column));
+        assert_eq!((1, 4), (lines.location(5).line, lines.location(5).column));
+        assert_eq!((2, 2), (lines.location(8).line, lines.location(8).column));
+        assert_eq!((2, 4), (lines.location(11).line, lines.location(11).column));
+    }
+
+    #[test]
+    fn test_location_offset_from() {
+        let origin = at(20, 3, 8);
+        assert_eq!(at(22, 3, 10), at(2, 1, 3).offset_from(origin));
+        assert_eq!(at(30, 4, 5), at(10, 2, 5).offset_from(origin));
+    }
+
+    fn at(offset: usize, line: usize, column: usize) -> Location {
+        Location {
+            offset,
+            line,
+            column,
+        }
+    }
+}
@@ -1,7 +1,11 @@
 use std::collections::HashMap;
+use std::ops::BitOr;
 
-use crate::css::{Rule, Selector, SimpleSelector, Specificity, Stylesheet, Value};
-use crate::dom::{ElementData, Node, NodeType};
+use crate::css::{
+    self, AttributeOperator, AttributeSelector, Combinator, Declaration, PseudoClass,
+    PseudoElement, Rule, Selector, SimpleSelector, Specificity, Stylesheet, Value,
+};
+use crate::dom::{ElementData, NodeId, NodeRef, NodeType};
 
 /// Map from CSS property names to values.
 type PropertyMap = HashMap<String, Value>;
@@ -12,10 +16,36 @@ pub enum Display {
     None,
 }
 
+/// The value of the `white-space` property.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WhiteSpace {
+    Normal,
+    Pre,
+    Nowrap,
+    PreWrap,
+    PreLine,
+}
+
+impl WhiteSpace {
+    /// Whether runs of spaces and tabs collapse to a single space.
+    pub fn collapses_spaces(self) -> bool {
+        matches!(
+            self,
+            WhiteSpace::Normal | WhiteSpace::Nowrap | WhiteSpace::PreLine
+        )
+    }
+}
+
 /// A node with associated style data.
 #[derive(Debug, PartialEq)]
 pub struct StyledNode<'a> {
-    pub node: &'a Node, // pointer to a DOM node
+    pub node: NodeRef<'a>, // pointer to a DOM node
+    /// Set on the node for a `::before` or `::after` pseudo-element, whose `node` is the element
+    /// that generated it.
+    pub pseudo_element: Option<PseudoElement>,
+    /// The text generated by the `content` property, for the child of a pseudo-element that
+    /// holds it. Its `node` is the element that generated the pseudo-element.
+    pub generated_text: Option<String>,
     pub specified_values: PropertyMap,
     pub children: Vec<StyledNode<'a>>,
 }
@@ -23,7 +53,7 @@ pub struct StyledNode<'a> {
 impl<'a> StyledNode<'a> {
     /// Return the specified value of a property if it exists, otherwise `None`.
     pub fn value(&self, name: &str) -> Option<Value> {
-        self.specified_values.get(name).map(|v| v.clone())
+        self.specified_values.get(name).cloned()
     }
 
     /// Return the specified value of property `name`, or property `fallback_name` if that doesn't
@@ -44,68 +74,593 @@ impl<'a> StyledNode<'a> {
             _ => Display::Inline,
         }
     }
+
+    /// The value of the `white-space` property (default to normal).
+    pub fn white_space(&self) -> WhiteSpace {
+        match self.value("white-space") {
+            Some(Value::Keyword(s)) => match &*s {
+                "pre" => WhiteSpace::Pre,
+                "nowrap" => WhiteSpace::Nowrap,
+                "pre-wrap" => WhiteSpace::PreWrap,
+                "pre-line" => WhiteSpace::PreLine,
+                _ => WhiteSpace::Normal,
+            },
+            _ => WhiteSpace::Normal,
+        }
+    }
+
+    /// The text of a text node, or the text generated for a pseudo-element.
+    pub fn text(&self) -> Option<&str> {
+        match (&self.generated_text, self.node.node_type()) {
+            (Some(text), _) => Some(text),
+            (None, NodeType::Text(text)) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The text of this node as it is rendered: hidden elements are left out, whitespace is
+    /// processed according to `white-space`, and block elements and `<br>` start new lines.
+    pub fn inner_text(&self) -> String {
+        let mut lines = vec![String::new()];
+        self.collect_lines(&mut lines);
+
+        let lines: Vec<&str> = lines
+            .iter()
+            .map(|line| line.trim_end_matches(' '))
+            .collect();
+        let first = lines.iter().position(|line| !line.is_empty());
+        let last = lines.iter().rposition(|line| !line.is_empty());
+        match (first, last) {
+            (Some(first), Some(last)) => lines[first..=last].join("\n"),
+            _ => String::new(),
+        }
+    }
+
+    fn collect_lines(&self, lines: &mut Vec<String>) {
+        // Generated content isn't part of the text, just as in browsers.
+        if self.pseudo_element.is_some() {
+            return;
+        }
+        match self.node.node_type() {
+            NodeType::Text(text) => {
+                let white_space = self.white_space();
+                for c in collapse_white_space(text, white_space).chars() {
+                    let line = lines.last_mut().unwrap();
+                    if c == '\n' {
+                        lines.push(String::new());
+                    } else if c != ' '
+                        || !white_space.collapses_spaces()
+                        || !(line.is_empty() || line.ends_with(' '))
+                    {
+                        line.push(c);
+                    }
+                }
+            }
+            NodeType::Element(elem) if elem.tag_name == "br" => lines.push(String::new()),
+            NodeType::Element(elem) if NOT_RENDERED.contains(&&elem.tag_name[..]) => {}
+            NodeType::Element(_) => match self.display() {
+                Display::None => {}
+                Display::Inline => {
+                    for child in &self.children {
+                        child.collect_lines(lines);
+                    }
+                }
+                Display::Block => {
+                    start_line(lines);
+                    for child in &self.children {
+                        child.collect_lines(lines);
+                    }
+                    start_line(lines);
+                }
+            },
+            NodeType::Comment(_) => {}
+        }
+    }
+}
+
+/// Elements whose contents are never rendered. Browsers hide these with `display: none` in
+/// their default stylesheet, which this engine doesn't have.
+const NOT_RENDERED: &[&str] = &["head", "script", "style", "template"];
+
+/// Start a new line at a block boundary, unless the current line has no text yet.
+fn start_line(lines: &mut Vec<String>) {
+    if !lines.last().unwrap().trim().is_empty() {
+        lines.push(String::new());
+    }
+}
+
+/// Process the whitespace in `text` as the given `white-space` value requires. Under `normal`
+/// and `nowrap` every run of whitespace becomes a single space; `pre-line` does the same but
+/// keeps newlines, dropping the spaces around them; `pre` and `pre-wrap` keep the text as is.
+///
+/// Spaces at the start and end of `text` are kept, since they may separate it from
+/// neighbouring inline content. Dropping the ones that don't is left to the caller.
+pub fn collapse_white_space(text: &str, white_space: WhiteSpace) -> String {
+    let keep_newlines = match white_space {
+        WhiteSpace::Pre | WhiteSpace::PreWrap => return text.to_string(),
+        WhiteSpace::Normal | WhiteSpace::Nowrap => false,
+        WhiteSpace::PreLine => true,
+    };
+
+    let mut result = String::with_capacity(text.len());
+    let mut space = false;
+    for c in text.chars() {
+        if keep_newlines && c == '\n' {
+            result.push('\n');
+            space = false;
+        } else if matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0C') {
+            space = space || !(keep_newlines && result.ends_with('\n'));
+        } else {
+            if space {
+                result.push(' ');
+                space = false;
+            }
+            result.push(c);
+        }
+    }
+    if space {
+        result.push(' ');
+    }
+    result
 }
 
+/// Properties whose values pass from an element to its children when not specified on them.
+const INHERITED_PROPERTIES: &[&str] = &["white-space"];
+
 type MatchedRule<'a> = (Specificity, &'a Rule);
 
+/// Interaction states of an element, which dynamic pseudo-classes like `:hover` match. States
+/// combine with `|`, e.g. `ElementState::HOVER | ElementState::ACTIVE`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ElementState(u8);
+
+impl ElementState {
+    /// The pointer is over the element. Its ancestors are hovered too.
+    pub const HOVER: ElementState = ElementState(1);
+    /// The element has keyboard focus.
+    pub const FOCUS: ElementState = ElementState(1 << 1);
+    /// The element is being activated, e.g. pressed. Its ancestors are active too.
+    pub const ACTIVE: ElementState = ElementState(1 << 2);
+    /// A checkbox or radio button is checked, or an option is selected.
+    pub const CHECKED: ElementState = ElementState(1 << 3);
+    /// A form control is disabled.
+    pub const DISABLED: ElementState = ElementState(1 << 4);
+    /// A link has been visited.
+    pub const VISITED: ElementState = ElementState(1 << 5);
+
+    /// Does this include all of the states in `other`?
+    pub fn contains(self, other: ElementState) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for ElementState {
+    type Output = ElementState;
+
+    fn bitor(self, other: ElementState) -> ElementState {
+        ElementState(self.0 | other.0)
+    }
+}
+
+/// Map from elements to their interaction states, supplied by the embedder. Elements that
+/// aren't in the map are in no state beyond what their attributes say, such as `checked`.
+pub type StateMap = HashMap<NodeId, ElementState>;
+
 /// Apply a stylesheet to an entire DOM tree, returning a StyledNode tree.
-pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
+pub fn style_tree<'a>(root: NodeRef<'a>, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
+    style_tree_with_states(root, stylesheet, &StateMap::new())
+}
+
+/// Apply a stylesheet to an entire DOM tree, with elements in the given interaction `states`.
+pub fn style_tree_with_states<'a>(
+    root: NodeRef<'a>,
+    stylesheet: &'a Stylesheet,
+    states: &StateMap,
+) -> StyledNode<'a> {
+    style_node(
+        root,
+        stylesheet,
+        states,
+        &HashMap::new(),
+        &mut Counters::default(),
+    )
+}
+
+// Nodes are styled in document order, so that `counters` holds the counters in scope at each
+// one.
+fn style_node<'a>(
+    node: NodeRef<'a>,
+    stylesheet: &'a Stylesheet,
+    states: &StateMap,
+    parent_values: &PropertyMap,
+    counters: &mut Counters,
+) -> StyledNode<'a> {
+    let NodeType::Element(elem) = node.node_type() else {
+        let mut values = HashMap::new();
+        inherit(&mut values, parent_values);
+        return StyledNode {
+            node,
+            pseudo_element: None,
+            generated_text: None,
+            specified_values: values,
+            children: vec![],
+        };
+    };
+
+    let mut values = specified_values(node, elem, stylesheet, states, None);
+    inherit(&mut values, parent_values);
+    counters.update(&values, node.parent().map(|parent| parent.id()));
+
+    let mut children = vec![];
+    children.extend(style_pseudo_element(
+        node,
+        elem,
+        PseudoElement::Before,
+        stylesheet,
+        states,
+        &values,
+        counters,
+    ));
+    for child in node.children() {
+        if !matches!(child.node_type(), NodeType::Comment(_)) {
+            children.push(style_node(child, stylesheet, states, &values, counters));
+        }
+    }
+    children.extend(style_pseudo_element(
+        node,
+        elem,
+        PseudoElement::After,
+        stylesheet,
+        states,
+        &values,
+        counters,
+    ));
+    counters.end_scope(node.id());
+
     StyledNode {
-        node: root,
-        specified_values: match root.node_type {
-            NodeType::Element(ref elem) => specified_values(elem, stylesheet),
-            NodeType::Text(_) => HashMap::new(),
-        },
-        children: root
-            .children
+        node,
+        pseudo_element: None,
+        generated_text: None,
+        specified_values: values,
+        children,
+    }
+}
+
+// Style the `::before` or `::after` pseudo-element of `elem`, or return `None` if its `content`
+// doesn't generate one.
+fn style_pseudo_element<'a>(
+    node: NodeRef<'a>,
+    elem: &ElementData,
+    pseudo_element: PseudoElement,
+    stylesheet: &Stylesheet,
+    states: &StateMap,
+    parent_values: &PropertyMap,
+    counters: &mut Counters,
+) -> Option<StyledNode<'a>> {
+    let mut values = specified_values(node, elem, stylesheet, states, Some(pseudo_element));
+    let content = match values.get("content") {
+        None | Some(Value::Keyword(_)) => return None, // `none` or `normal`
+        Some(content) => content.clone(),
+    };
+    if values.get("display") == Some(&Value::Keyword("none".to_string())) {
+        return None;
+    }
+    inherit(&mut values, parent_values);
+    counters.update(&values, Some(node.id()));
+
+    let text = generated_text(&content, elem, counters);
+    let mut text_values = HashMap::new();
+    inherit(&mut text_values, &values);
+    let children = if text.is_empty() {
+        vec![]
+    } else {
+        vec![StyledNode {
+            node,
+            pseudo_element: None,
+            generated_text: Some(text),
+            specified_values: text_values,
+            children: vec![],
+        }]
+    };
+    Some(StyledNode {
+        node,
+        pseudo_element: Some(pseudo_element),
+        generated_text: None,
+        specified_values: values,
+        children,
+    })
+}
+
+/// Copy the values of inherited properties that `values` doesn't specify from `parent_values`.
+fn inherit(values: &mut PropertyMap, parent_values: &PropertyMap) {
+    for &name in INHERITED_PROPERTIES {
+        if let Some(value) = parent_values.get(name) {
+            values
+                .entry(name.to_string())
+                .or_insert_with(|| value.clone());
+        }
+    }
+}
+
+/// The text that a `content` value generates for a pseudo-element of `elem`.
+fn generated_text(content: &Value, elem: &ElementData, counters: &Counters) -> String {
+    let items = match content {
+        Value::List(items) => &items[..],
+        content => std::slice::from_ref(content),
+    };
+    let mut text = String::new();
+    for item in items {
+        match item {
+            Value::Str(s) => text.push_str(s),
+            Value::Attr(name) => text.push_str(elem.attributes.get(name).map_or("", |v| v)),
+            Value::Counter(name) => {
+                let value = counters.values(name).last().copied().unwrap_or(0);
+                text.push_str(&value.to_string());
+            }
+            Value::Counters(name, separator) => {
+                let mut values = counters.values(name);
+                if values.is_empty() {
+                    values.push(0);
+                }
+                let values: Vec<String> = values.iter().map(i32::to_string).collect();
+                text.push_str(&values.join(separator));
+            }
+            _ => {}
+        }
+    }
+    text
+}
+
+/// The CSS counters in scope while styling the tree in document order. Each counter instance
+/// records the element whose children it is scoped to: a counter reset on an element lasts
+/// until the end of that element's parent, and is seen by the element's later siblings and
+/// their descendants.
+#[derive(Default)]
+struct Counters(Vec<(String, i32, Option<NodeId>)>);
+
+impl Counters {
+    /// Apply the `counter-reset` and `counter-increment` values of an element whose parent is
+    /// `scope`.
+    fn update(&mut self, values: &PropertyMap, scope: Option<NodeId>) {
+        for (name, value) in counter_changes(values.get("counter-reset")) {
+            match self.0.iter_mut().rev().find(|counter| counter.0 == name) {
+                // Resetting a counter that a sibling created replaces it instead of nesting.
+                Some(counter) if counter.2 == scope => counter.1 = value,
+                _ => self.0.push((name, value, scope)),
+            }
+        }
+        for (name, value) in counter_changes(values.get("counter-increment")) {
+            match self.0.iter_mut().rev().find(|counter| counter.0 == name) {
+                Some(counter) => counter.1 = counter.1.saturating_add(value),
+                // Incrementing a counter that doesn't exist creates it, starting from zero.
+                None => self.0.push((name, value, scope)),
+            }
+        }
+    }
+
+    /// The values of all counters called `name`, outermost first.
+    fn values(&self, name: &str) -> Vec<i32> {
+        self.0
             .iter()
-            .map(|child| style_tree(child, stylesheet))
-            .collect(),
+            .filter(|counter| counter.0 == name)
+            .map(|counter| counter.1)
+            .collect()
+    }
+
+    /// Remove the counters created by the children of `node`, whose scope ends with it.
+    fn end_scope(&mut self, node: NodeId) {
+        self.0.retain(|counter| counter.2 != Some(node));
     }
 }
 
-/// Apply styles to a single element, returning the specified values.
-fn specified_values(elem: &ElementData, stylesheet: &Stylesheet) -> PropertyMap {
-    let mut values = HashMap::new();
-    let mut rules = matching_rules(elem, stylesheet);
+/// The counter names and values in a `counter-reset` or `counter-increment` value.
+fn counter_changes(value: Option<&Value>) -> Vec<(String, i32)> {
+    let Some(Value::List(items)) = value else {
+        return vec![];
+    };
+    items
+        .chunks(2)
+        .filter_map(|pair| match pair {
+            [Value::Keyword(name), Value::Number(value)] => Some((name.clone(), *value as i32)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Apply styles to a single element, or to its `pseudo_element` if that is given, returning
+/// the specified values.
+fn specified_values(
+    node: NodeRef,
+    elem: &ElementData,
+    stylesheet: &Stylesheet,
+    states: &StateMap,
+    pseudo_element: Option<PseudoElement>,
+) -> PropertyMap {
+    let mut rules = matching_rules(node, stylesheet, states, pseudo_element);
 
     // Go through the rules from lowest to highest specificity.
-    rules.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
-    for (_, rule) in rules {
-        for declaration in &rule.declarations {
+    rules.sort_by_key(|&(a, _)| a);
+    let mut declarations: Vec<&Declaration> = rules
+        .iter()
+        .flat_map(|(_, rule)| &rule.declarations)
+        .collect();
+
+    // Declarations in the `style` attribute take precedence over all rules. They don't apply to
+    // pseudo-elements.
+    let style_declarations = match (pseudo_element, elem.attributes.get("style")) {
+        (None, Some(style)) => css::parse_declarations(style.clone()),
+        _ => vec![],
+    };
+    declarations.extend(&style_declarations);
+
+    // `!important` declarations take precedence over normal ones, in the same order.
+    let mut values = HashMap::new();
+    for important in [false, true] {
+        for declaration in declarations.iter().filter(|d| d.important == important) {
             values.insert(declaration.name.clone(), declaration.value.clone());
         }
     }
     values
 }
 
-/// Find all CSS rules that match the given element.
-fn matching_rules<'a>(elem: &ElementData, stylesheet: &'a Stylesheet) -> Vec<MatchedRule<'a>> {
+/// Find all CSS rules that match the given element, or its `pseudo_element` if that is given.
+fn matching_rules<'a>(
+    elem: NodeRef,
+    stylesheet: &'a Stylesheet,
+    states: &StateMap,
+    pseudo_element: Option<PseudoElement>,
+) -> Vec<MatchedRule<'a>> {
     stylesheet
         .rules
         .iter()
-        .filter_map(|rule| match_rule(elem, rule))
+        .filter_map(|rule| match_rule(elem, rule, states, pseudo_element))
         .collect()
 }
 
-/// If `rule` matches `elem`, return a `MatchedRule`. Otherwise return `None`.
-fn match_rule<'a>(elem: &ElementData, rule: &'a Rule) -> Option<MatchedRule<'a>> {
+/// If `rule` matches `elem`, or its `pseudo_element` if that is given, return a `MatchedRule`.
+/// Otherwise return `None`.
+fn match_rule<'a>(
+    elem: NodeRef,
+    rule: &'a Rule,
+    states: &StateMap,
+    pseudo_element: Option<PseudoElement>,
+) -> Option<MatchedRule<'a>> {
     // Find the first (highest-specificity) matching selector.
     rule.selectors
         .iter()
-        .find(|selector| matches(elem, *selector))
+        .find(|selector| matches_pseudo_element(elem, selector, states, pseudo_element))
         .map(|selector| (selector.specificity(), rule))
 }
 
-fn matches(elem: &ElementData, selector: &Selector) -> bool {
+/// Does `selector` match `elem`? Selectors are matched from right to left, starting with the
+/// element itself and moving out through its ancestors and siblings.
+pub fn matches(elem: NodeRef, selector: &Selector) -> bool {
+    matches_with_states(elem, selector, &StateMap::new())
+}
+
+/// Does `selector` match `elem`, with elements in the given interaction `states`? Selectors
+/// ending in a pseudo-element never match the element itself.
+pub fn matches_with_states(elem: NodeRef, selector: &Selector, states: &StateMap) -> bool {
+    matches_pseudo_element(elem, selector, states, None)
+}
+
+/// Does `selector` match `pseudo_element` of `elem`, or `elem` itself if that is `None`?
+fn matches_pseudo_element(
+    elem: NodeRef,
+    selector: &Selector,
+    states: &StateMap,
+    pseudo_element: Option<PseudoElement>,
+) -> bool {
     match *selector {
-        Selector::Simple(ref simple_selector) => matches_simple_selector(elem, simple_selector),
+        Selector::Simple(ref simple_selector) => {
+            simple_selector.pseudo_element == pseudo_element
+                && matches_simple_selector(elem, simple_selector, states)
+        }
+        Selector::Complex {
+            ref subject,
+            ref context,
+        } => {
+            subject.pseudo_element == pseudo_element
+                && matches_simple_selector(elem, subject, states)
+                && matches_context(elem, context, states)
+        }
     }
 }
 
-fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> bool {
-    // Check type selector
-    if selector.tag_name.iter().any(|name| elem.tag_name != *name) {
+/// Does `context`, the rest of a selector from right to left, match relative to `elem`?
+fn matches_context(
+    elem: NodeRef,
+    context: &[(Combinator, SimpleSelector)],
+    states: &StateMap,
+) -> bool {
+    match_context(elem, context, states) == ContextMatch::Matched
+}
+
+/// The outcome of matching part of a selector, which says how far back the caller has to go to
+/// try another candidate. Without this, a selector with many descendant combinators would try
+/// every combination of ancestors, taking exponential time. This is the pruning that Servo's
+/// selector matching uses.
+#[derive(Clone, Copy, PartialEq)]
+enum ContextMatch {
+    Matched,
+    /// Another candidate for the closest sibling combinator might still match.
+    RestartFromClosestLaterSibling,
+    /// Only another candidate for the closest descendant combinator might still match.
+    RestartFromClosestDescendant,
+    /// No other candidate can match, so the whole selector fails.
+    NotMatchedGlobally,
+}
+
+fn match_context(
+    elem: NodeRef,
+    context: &[(Combinator, SimpleSelector)],
+    states: &StateMap,
+) -> ContextMatch {
+    let Some(((combinator, selector), rest)) = context.split_first() else {
+        return ContextMatch::Matched;
+    };
+    let candidates: Box<dyn Iterator<Item = NodeRef>> = match combinator {
+        Combinator::Descendant => Box::new(ancestors(elem)),
+        Combinator::Child => Box::new(ancestors(elem).take(1)),
+        Combinator::NextSibling => Box::new(previous_siblings(elem).take(1)),
+        Combinator::SubsequentSibling => Box::new(previous_siblings(elem)),
+    };
+    // Try the elements that the combinator allows, since a later part of the selector might
+    // only match relative to some of them, but stop once no other candidate can help.
+    for candidate in candidates {
+        let result = if matches_simple_selector(candidate, selector, states) {
+            match_context(candidate, rest, states)
+        } else {
+            ContextMatch::RestartFromClosestLaterSibling
+        };
+        match (result, combinator) {
+            (ContextMatch::Matched | ContextMatch::NotMatchedGlobally, _)
+            | (_, Combinator::NextSibling) => return result,
+            (_, Combinator::Child) => return ContextMatch::RestartFromClosestDescendant,
+            (ContextMatch::RestartFromClosestDescendant, Combinator::SubsequentSibling) => {
+                return result
+            }
+            _ => {}
+        }
+    }
+    // If there are no more ancestors, trying other candidates further right can't find any.
+    match combinator {
+        Combinator::Descendant | Combinator::Child => ContextMatch::NotMatchedGlobally,
+        Combinator::NextSibling | Combinator::SubsequentSibling => {
+            ContextMatch::RestartFromClosestDescendant
+        }
+    }
+}
+
+/// The element ancestors of `node`, starting with its parent.
+fn ancestors(node: NodeRef) -> impl Iterator<Item = NodeRef> {
+    std::iter::successors(node.parent(), |node| node.parent())
+        .filter(|node| node.as_element().is_some())
+}
+
+/// The element siblings before `node`, starting with the closest.
+fn previous_siblings(node: NodeRef) -> impl Iterator<Item = NodeRef> {
+    std::iter::successors(node.prev_sibling(), |node| node.prev_sibling())
+        .filter(|node| node.as_element().is_some())
+}
+
+/// The element siblings after `node`, starting with the closest.
+fn following_siblings(node: NodeRef) -> impl Iterator<Item = NodeRef> {
+    std::iter::successors(node.next_sibling(), |node| node.next_sibling())
+        .filter(|node| node.as_element().is_some())
+}
+
+fn matches_simple_selector(node: NodeRef, selector: &SimpleSelector, states: &StateMap) -> bool {
+    let Some(elem) = node.as_element() else {
+        return false;
+    };
+
+    // Check type selector. HTML tag names are case-insensitive.
+    if selector
+        .tag_name
+        .iter()
+        .any(|name| !elem.tag_name.eq_ignore_ascii_case(name))
+    {
         return false;
     }
 
@@ -124,19 +679,169 @@ fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> boo
         return false;
     }
 
+    // Check attribute selectors
+    if !selector
+        .attributes
+        .iter()
+        .all(|attribute| matches_attribute(elem, attribute))
+    {
+        return false;
+    }
+
+    // Check pseudo-classes
+    if !selector
+        .pseudo_classes
+        .iter()
+        .all(|pseudo_class| matches_pseudo_class(node, pseudo_class, states))
+    {
+        return false;
+    }
+
     // We didn't find any non-matching selector components.
     true
 }
 
+fn matches_pseudo_class(elem: NodeRef, pseudo_class: &PseudoClass, states: &StateMap) -> bool {
+    let matches = |node, selector: &Selector| matches_with_states(node, selector, states);
+    let matches_any = |selectors: &[Selector], node| {
+        selectors.is_empty() || selectors.iter().any(|selector| matches(node, selector))
+    };
+    let tag_name = elem.as_element().map_or("", |e| &e.tag_name);
+    let has_attribute = |name| {
+        elem.as_element()
+            .is_some_and(|e| e.attributes.contains_key(name))
+    };
+    let is_link = || {
+        ["a", "area", "link"]
+            .iter()
+            .any(|name| tag_name.eq_ignore_ascii_case(name))
+            && has_attribute("href")
+    };
+    match pseudo_class {
+        PseudoClass::Hover => has_state(elem, states, ElementState::HOVER),
+        PseudoClass::Focus => has_state(elem, states, ElementState::FOCUS),
+        PseudoClass::Active => has_state(elem, states, ElementState::ACTIVE),
+        // Form controls start out checked or disabled according to their attributes.
+        PseudoClass::Checked => {
+            has_state(elem, states, ElementState::CHECKED)
+                || (tag_name.eq_ignore_ascii_case("input") && has_attribute("checked"))
+                || (tag_name.eq_ignore_ascii_case("option") && has_attribute("selected"))
+        }
+        PseudoClass::Disabled => {
+            has_state(elem, states, ElementState::DISABLED)
+                || (FORM_CONTROLS
+                    .iter()
+                    .any(|name| tag_name.eq_ignore_ascii_case(name))
+                    && has_attribute("disabled"))
+        }
+        PseudoClass::Link => is_link() && !has_state(elem, states, ElementState::VISITED),
+        PseudoClass::Visited => is_link() && has_state(elem, states, ElementState::VISITED),
+        PseudoClass::Root => elem.parent().is_none(),
+        PseudoClass::Empty => elem.children().all(|child| match child.node_type() {
+            NodeType::Element(_) => false,
+            NodeType::Text(text) => text.is_empty(),
+            NodeType::Comment(_) => true,
+        }),
+        PseudoClass::FirstChild => previous_siblings(elem).next().is_none(),
+        PseudoClass::LastChild => following_siblings(elem).next().is_none(),
+        PseudoClass::OnlyChild => {
+            previous_siblings(elem).next().is_none() && following_siblings(elem).next().is_none()
+        }
+        PseudoClass::NthChild(nth, selectors) => {
+            matches_any(selectors, elem)
+                && nth.matches(
+                    1 + previous_siblings(elem)
+                        .filter(|sibling| matches_any(selectors, *sibling))
+                        .count() as i32,
+                )
+        }
+        PseudoClass::NthLastChild(nth, selectors) => {
+            matches_any(selectors, elem)
+                && nth.matches(
+                    1 + following_siblings(elem)
+                        .filter(|sibling| matches_any(selectors, *sibling))
+                        .count() as i32,
+                )
+        }
+        PseudoClass::NthOfType(nth) => {
+            let same_type = |sibling: &NodeRef| {
+                sibling
+                    .as_element()
+                    .is_some_and(|e| e.tag_name.eq_ignore_ascii_case(tag_name))
+            };
+            nth.matches(1 + previous_siblings(elem).filter(same_type).count() as i32)
+        }
+        PseudoClass::Not(selectors) => !selectors.iter().any(|selector| matches(elem, selector)),
+        PseudoClass::Is(selectors) | PseudoClass::Where(selectors) => {
+            selectors.iter().any(|selector| matches(elem, selector))
+        }
+    }
+}
+
+/// Elements that the `disabled` attribute applies to.
+const FORM_CONTROLS: &[&str] = &[
+    "button", "fieldset", "input", "optgroup", "option", "select", "textarea",
+];
+
+/// Is `elem` in `state`? Hover and active states also apply to the ancestors of the elements
+/// they are set on.
+fn has_state(elem: NodeRef, states: &StateMap, state: ElementState) -> bool {
+    let applies_to_ancestors = state == ElementState::HOVER || state == ElementState::ACTIVE;
+    states.iter().any(|(&id, &element_state)| {
+        element_state.contains(state)
+            && (id == elem.id()
+                || applies_to_ancestors
+                    && ancestors(elem.document().get(id)).any(|a| a.id() == elem.id()))
+    })
+}
+
+fn matches_attribute(elem: &ElementData, selector: &AttributeSelector) -> bool {
+    let Some(value) = elem.attributes.get(&selector.name) else {
+        return false;
+    };
+    let Some(operator) = selector.operator else {
+        return true;
+    };
+    let (value, expected) = if selector.case_insensitive {
+        (
+            value.to_ascii_lowercase(),
+            selector.value.to_ascii_lowercase(),
+        )
+    } else {
+        (value.clone(), selector.value.clone())
+    };
+    match operator {
+        AttributeOperator::Equals => value == expected,
+        AttributeOperator::Includes => value.split_ascii_whitespace().any(|word| word == expected),
+        AttributeOperator::DashMatch => {
+            value == expected
+                || value
+                    .strip_prefix(&expected)
+                    .is_some_and(|rest| rest.starts_with('-'))
+        }
+        // An empty value never matches a substring test.
+        AttributeOperator::Prefix => !expected.is_empty() && value.starts_with(&expected),
+        AttributeOperator::Suffix => !expected.is_empty() && value.ends_with(&expected),
+        AttributeOperator::Substring => !expected.is_empty() && value.contains(&expected),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
 
-    use super::{style_tree, StyledNode};
+    use std::path::Path;
+
+    use super::{
+        collapse_white_space, matches, matches_with_states, style_tree, style_tree_with_states,
+        ElementState, StateMap, StyledNode, WhiteSpace,
+    };
     use crate::css;
-    use crate::css::{Color, Value};
-    use crate::dom::{elem, text};
+    use crate::css::{Color, PseudoElement, Unit, Value};
+    use crate::dom::text;
     use crate::html;
+    use crate::loader::{FileLoader, StylesheetLoader};
+    use crate::query::query_selector;
 
     #[test]
     fn test_style_tree_overwrite() {
@@ -153,9 +858,13 @@ mod tests {
         }
         "#,
         );
-        let root = html::parse(html_source);
+        let document = html::parse(html_source);
+        let root = document.root();
         let css = css::parse(css_source);
 
+        // The parser wraps the paragraph in <html> and <body>.
+        let p = root.children().nth(1).unwrap().first_child().unwrap();
+
         let mut specified_values = HashMap::new();
         specified_values.insert(
             String::from("color"),
@@ -168,14 +877,391 @@ mod tests {
         );
         let text = text(String::from("Hello"));
         let expected = StyledNode {
-            node: &root,
+            node: p,
+            pseudo_element: None,
+            generated_text: None,
             specified_values,
             children: vec![StyledNode {
-                node: &text,
+                node: text.root(),
+                pseudo_element: None,
+                generated_text: None,
                 specified_values: HashMap::new(),
                 children: vec![],
             }],
         };
-        assert_eq!(expected, style_tree(&root, &css));
+        let styled_root = style_tree(root, &css);
+        assert_eq!(expected, styled_root.children[1].children[0]);
+    }
+
+    #[test]
+    fn test_style_tree_skips_comments() {
+        let document = html::parse(String::from("<div><!-- note -->Hi</div>"));
+        let css = css::parse(String::new());
+
+        let styled_root = style_tree(document.root(), &css);
+        let div = &styled_root.children[1].children[0];
+        assert_eq!(1, div.children.len());
+        assert_eq!(
+            div.node.last_child().map(|node| node.id()),
+            Some(div.children[0].node.id())
+        );
+    }
+
+    #[test]
+    fn test_type_selector_ignores_case() {
+        let document = html::parse(String::from("<P>Hello</P>"));
+        let css = css::parse(String::from("P { color: #cc0000; }"));
+
+        let styled_root = style_tree(document.root(), &css);
+        let p = &styled_root.children[1].children[0];
+        assert_eq!(
+            Some(Value::ColorValue(Color {
+                r: 204,
+                g: 0,
+                b: 0,
+                a: 255,
+            })),
+            p.value("color")
+        );
+    }
+
+    #[test]
+    fn test_embedded_and_inline_styles() {
+        let html_source = String::from(
+            r#"<style>p { color: #cccccc; } #a { color: #cc0000; }</style>
+<p id="a" style="color: #00cc00; width: 10px">Hello</p>"#,
+        );
+        let document = html::parse(html_source);
+        let mut css = css::parse(String::from("p { width: 20px; height: 30px; }"));
+        let mut loader = StylesheetLoader::new(&FileLoader);
+        for embedded in loader.document_stylesheets(document.root(), Path::new("test.html")) {
+            css.rules.extend(embedded.rules);
+        }
+
+        let styled_root = style_tree(document.root(), &css);
+        let p = &styled_root.children[1].children[0];
+
+        let mut specified_values = HashMap::new();
+        specified_values.insert(
+            String::from("color"),
+            Value::ColorValue(Color {
+                r: 0,
+                g: 204,
+                b: 0,
+                a: 255,
+            }),
+        );
+        specified_values.insert(String::from("width"), Value::Length(10.0, Unit::Px));
+        specified_values.insert(String::from("height"), Value::Length(30.0, Unit::Px));
+        assert_eq!(specified_values, p.specified_values);
+    }
+
+    #[test]
+    fn test_important_declarations() {
+        let document = html::parse(String::from(
+            r#"<p id="a" style="width: 3px; height: 3px; display: inline !important">Hi</p>"#,
+        ));
+        let stylesheet = css::parse(String::from(
+            "p { width: 1px !important; height: 1px !important; }
+            #a { width: 2px; height: 2px !IMPORTANT; display: block !important; }",
+        ));
+        let styled_root = style_tree(document.root(), &stylesheet);
+        let p = &styled_root.children[1].children[0];
+
+        // Important declarations beat normal ones, however specific, and among themselves
+        // follow the usual order.
+        assert_eq!(Some(Value::Length(1.0, Unit::Px)), p.value("width"));
+        assert_eq!(Some(Value::Length(2.0, Unit::Px)), p.value("height"));
+        assert_eq!(
+            Some(Value::Keyword(String::from("inline"))),
+            p.value("display")
+        );
+    }
+
+    #[test]
+    fn test_inner_text() {
+        let document = html::parse(String::from(
+            r#"<title>Ignored</title><style>p { display: block; }</style>
+<div class="block">Hello,
+  there   <em>inline</em>!<p>Para<br><br>after break</p><span class="none">hidden</span>tail</div>"#,
+        ));
+        let mut stylesheet = css::parse(String::from(
+            "div.block { display: block; } span.none { display: none; }",
+        ));
+        let mut loader = StylesheetLoader::new(&FileLoader);
+        for embedded in loader.document_stylesheets(document.root(), Path::new("test.html")) {
+            stylesheet.rules.extend(embedded.rules);
+        }
+
+        let styled_root = style_tree(document.root(), &stylesheet);
+        assert_eq!(
+            "Hello, there inline!\nPara\n\nafter break\ntail",
+            styled_root.inner_text()
+        );
+
+        let div = document.root().last_child().unwrap().first_child().unwrap();
+        assert_eq!(
+            "Hello,\n  there   inline!Paraafter breakhiddentail",
+            div.text_content()
+        );
+    }
+
+    #[test]
+    fn test_collapse_white_space() {
+        let text = " a \t b \n\n  c ";
+        assert_eq!(" a b c ", collapse_white_space(text, WhiteSpace::Normal));
+        assert_eq!(" a b c ", collapse_white_space(text, WhiteSpace::Nowrap));
+        assert_eq!(
+            " a b\n\nc ",
+            collapse_white_space(text, WhiteSpace::PreLine)
+        );
+        assert_eq!(text, collapse_white_space(text, WhiteSpace::Pre));
+        assert_eq!(text, collapse_white_space(text, WhiteSpace::PreWrap));
+    }
+
+    #[test]
+    fn test_white_space_inherited() {
+        let document = html::parse(String::from(
+            "<div><b>a</b> <i>b</i><p>  one\n  two </p></div>",
+        ));
+        let stylesheet = css::parse(String::from(
+            "div, p { display: block; } p { white-space: pre; }",
+        ));
+
+        let styled_root = style_tree(document.root(), &stylesheet);
+        let div = &styled_root.children[1].children[0];
+        assert_eq!(WhiteSpace::Normal, div.children[1].white_space());
+        assert_eq!(WhiteSpace::Pre, div.children[3].children[0].white_space());
+        assert_eq!("a b\n  one\n  two", div.inner_text());
+    }
+
+    #[test]
+    fn test_combinators() {
+        let document = html::parse(String::from(
+            r#"<div class="a"><ul><li><span id="s">x</span></li></ul></div><h1 id="h"></h1>text<p id="p1"></p><p id="p2"></p>"#,
+        ));
+        let by_id = |id: &str| {
+            query_selector(document.root(), &format!("#{}", id))
+                .unwrap()
+                .unwrap()
+        };
+        let matches_selector = |id: &str, selector: &str| {
+            let selectors = css::parse_selector_list(String::from(selector)).unwrap();
+            assert_eq!(1, selectors.len(), "{}", selector);
+            matches(by_id(id), &selectors[0])
+        };
+
+        assert!(matches_selector("s", "div span"));
+        assert!(matches_selector("s", ".a ul > li > span"));
+        assert!(matches_selector("s", "div li span"));
+        assert!(!matches_selector("s", "div > span"));
+        assert!(!matches_selector("s", "ul div span"));
+        // The text between the heading and the paragraph doesn't count as a sibling.
+        assert!(matches_selector("p1", "h1 + p"));
+        assert!(!matches_selector("p2", "h1 + p"));
+        assert!(matches_selector("p2", "h1 ~ p"));
+        assert!(matches_selector("p2", "body > h1 ~ #p1 + p"));
+        assert!(!matches_selector("h", "p ~ h1"));
+    }
+
+    #[test]
+    fn test_combinators_deep_tree() {
+        // Without pruning, failing to match this selector would retry every combination of the
+        // 30 ancestors for the eight `div`s, which takes far too long.
+        let source = format!(
+            "{}<p id=\"p\"></p>{}",
+            "<div>".repeat(30),
+            "</div>".repeat(30)
+        );
+        let document = html::parse(source);
+        let p = query_selector(document.root(), "#p").unwrap().unwrap();
+        let matches_selector = |selector: &str| {
+            matches(
+                p,
+                &css::parse_selector_list(String::from(selector)).unwrap()[0],
+            )
+        };
+
+        assert!(!matches_selector("span div div div div div div div div p"));
+        assert!(matches_selector("body div div div div div div div div p"));
+        assert!(!matches_selector(
+            "div > div div > span ~ div div div div div p"
+        ));
+        assert!(matches_selector(
+            "html div div > div div > div div div div > p"
+        ));
+    }
+
+    #[test]
+    fn test_attribute_selectors() {
+        let document = html::parse(String::from(
+            r#"<a id="a" href="https://example.com/a.PDF" class="x y" lang="en-US" data-size="Large">"#,
+        ));
+        let a = query_selector(document.root(), "#a").unwrap().unwrap();
+        let matches_selector = |selector: &str| {
+            let selectors = css::parse_selector_list(String::from(selector)).unwrap();
+            assert_eq!(1, selectors.len(), "{}", selector);
+            matches(a, &selectors[0])
+        };
+
+        assert!(matches_selector("[HREF]"));
+        assert!(!matches_selector("[title]"));
+        assert!(matches_selector("[data-size=Large]"));
+        assert!(!matches_selector("[data-size=large]"));
+        assert!(matches_selector("[data-size=large i]"));
+        assert!(matches_selector("[class~=y]"));
+        assert!(!matches_selector("[class~='x y']"));
+        assert!(matches_selector("[lang|=en]"));
+        assert!(!matches_selector("[lang|=e]"));
+        assert!(matches_selector("[href^='https:']"));
+        assert!(matches_selector("[href$='.pdf' i]"));
+        assert!(!matches_selector("[href$='.pdf' s]"));
+        assert!(matches_selector("a[href*=example][lang]"));
+        assert!(!matches_selector("[href*='']"));
+    }
+
+    #[test]
+    fn test_structural_pseudo_classes() {
+        let document = html::parse(String::from(
+            r#"<ul><li id="1" class="a"></li><!-- c --><li id="2">x</li><li id="3" class="a"><b></b></li><p id="4"></p><li id="5" class="a"></li></ul>"#,
+        ));
+        let ids = |selector: &str| -> Vec<String> {
+            let selectors = css::parse_selector_list(String::from(selector)).unwrap();
+            assert_eq!(1, selectors.len(), "{}", selector);
+            document
+                .root()
+                .pre_order()
+                .filter(|node| matches(*node, &selectors[0]))
+                .filter_map(|node| node.as_element()?.id().cloned())
+                .collect()
+        };
+
+        assert_eq!(vec!["1"], ids("li:first-child"));
+        assert_eq!(vec!["5"], ids("li:last-child"));
+        assert_eq!(Vec::<String>::new(), ids("li:only-child"));
+        assert_eq!(vec!["1", "4", "5"], ids("ul > :empty"));
+        assert_eq!(vec!["1", "3", "5"], ids("li:nth-child(odd)"));
+        assert_eq!(vec!["2", "4"], ids("ul > :nth-child(2n)"));
+        assert_eq!(vec!["3", "4", "5"], ids("ul > :nth-last-child(-n+3)"));
+        assert_eq!(vec!["3"], ids(":nth-child(2 of .a)"));
+        assert_eq!(vec!["3"], ids(":nth-last-child(2 of li.a)"));
+        assert_eq!(vec!["5"], ids("li:nth-of-type(4)"));
+        assert_eq!(ids("li"), ids("li:nth-child(n-2147483648)"));
+        assert_eq!(Vec::<String>::new(), ids("li:nth-child(-n-2147483648)"));
+        assert_eq!(vec!["2", "4"], ids("ul > :not(.a)"));
+        assert_eq!(vec!["2", "4"], ids(":is(li, p):where(:not(.a))"));
+        assert!(matches(
+            document.root(),
+            &css::parse_selector_list(String::from(":root")).unwrap()[0]
+        ));
+    }
+
+    #[test]
+    fn test_dynamic_pseudo_classes() {
+        let document = html::parse(String::from(
+            r#"<div id="d"><a id="a" href="x"><span id="s">Go</span></a><a id="b" href="y"></a><input id="c" type="checkbox" checked><input id="i" disabled></div>"#,
+        ));
+        let by_id = |id: &str| {
+            query_selector(document.root(), &format!("#{}", id))
+                .unwrap()
+                .unwrap()
+        };
+        let mut states = StateMap::new();
+        states.insert(by_id("s").id(), ElementState::HOVER | ElementState::FOCUS);
+        states.insert(by_id("b").id(), ElementState::VISITED);
+        states.insert(by_id("i").id(), ElementState::CHECKED);
+
+        let ids = |selector: &str| -> Vec<String> {
+            let selectors = css::parse_selector_list(String::from(selector)).unwrap();
+            assert_eq!(1, selectors.len(), "{}", selector);
+            document
+                .root()
+                .pre_order()
+                .filter(|node| matches_with_states(*node, &selectors[0], &states))
+                .filter_map(|node| node.as_element()?.id().cloned())
+                .collect()
+        };
+
+        // Hovering over the span hovers its ancestors too, but focus stays on the span.
+        assert_eq!(vec!["d", "a", "s"], ids("[id]:hover"));
+        assert_eq!(vec!["s"], ids(":focus"));
+        assert_eq!(Vec::<String>::new(), ids(":active"));
+        assert_eq!(vec!["a"], ids(":link"));
+        assert_eq!(vec!["b"], ids(":visited"));
+        assert_eq!(vec!["c", "i"], ids(":checked"));
+        assert_eq!(vec!["i"], ids(":disabled"));
+        assert!(!matches(
+            by_id("s"),
+            &css::parse_selector_list(String::from(":hover")).unwrap()[0]
+        ));
+
+        let stylesheet = css::parse(String::from("a:hover > span { color: #ff0000; }"));
+        let color = |states: &StateMap| {
+            let styled_root = style_tree_with_states(document.root(), &stylesheet, states);
+            let span = &styled_root.children[1].children[0].children[0].children[0];
+            span.value("color")
+        };
+        assert_eq!(None, color(&StateMap::new()));
+        assert_eq!(
+            Some(Value::ColorValue(Color {
+                r: 255,
+                g: 0,
+                b: 0,
+                a: 255,
+            })),
+            color(&states)
+        );
+    }
+
+    #[test]
+    fn test_generated_content() {
+        let document = html::parse(String::from(
+            r#"<ol><li title="One">a</li><li>b<ol><li>c</li></ol></li></ol><ol><li>d</li></ol>"#,
+        ));
+        let stylesheet = css::parse(String::from(
+            r#"
+            ol { counter-reset: item; }
+            li { counter-increment: item; }
+            li::before { content: counters(item, ".") ") "; }
+            li[title]:after { content: " (" attr(TITLE) ")"; white-space: pre; }
+            "#,
+        ));
+        let styled_root = style_tree(document.root(), &stylesheet);
+
+        // Collect the generated text and the text of the document in order.
+        fn collect(node: &StyledNode, text: &mut String) {
+            if let Some(s) = node.text() {
+                text.push_str(s);
+            }
+            for child in &node.children {
+                collect(child, text);
+            }
+        }
+        let mut text = String::new();
+        collect(&styled_root, &mut text);
+        assert_eq!("1) a (One)2) b2.1) c1) d", text);
+
+        // Pseudo-elements inherit from their element, and aren't part of its text.
+        let li = &styled_root.children[1].children[0].children[0];
+        assert_eq!(Some(PseudoElement::After), li.children[2].pseudo_element);
+        assert_eq!(WhiteSpace::Pre, li.children[2].children[0].white_space());
+        assert_eq!("a", li.inner_text());
+
+        // Selectors with a pseudo-element don't match the element itself.
+        let selector = &css::parse_selector_list(String::from("li::before")).unwrap()[0];
+        assert!(!matches(li.node, selector));
+
+        // Counter values are clamped rather than overflowing.
+        let document = html::parse(String::from("<ol><li>a</li></ol>"));
+        let stylesheet = css::parse(String::from(
+            r#"
+            ol { counter-reset: item 99999999999; }
+            li { counter-increment: item; }
+            li::before { content: counter(item); }
+            "#,
+        ));
+        let mut text = String::new();
+        collect(&style_tree(document.root(), &stylesheet), &mut text);
+        assert_eq!("2147483647a", text);
     }
 }